@@ -0,0 +1,56 @@
+/// What to open on start, from the command line: `awildtxt [+command] [path|-]...`
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    // `-` stands for the text read from stdin
+    pub paths: Vec<String>,
+    // Command line command to run in the first buffer, `+42` goes to line 42 and `+` to the last line
+    pub command: Option<String>,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args::default();
+        let mut only_paths = false;
+
+        for arg in args {
+            if only_paths || arg == "-" {
+                parsed.paths.push(arg);
+            } else if arg == "--" {
+                only_paths = true;
+            } else if let Some(command) = arg.strip_prefix('+') {
+                parsed.command = Some(String::from(if command.is_empty() { "$" } else { command }));
+            } else if arg.starts_with('-') {
+                return Err(format!("Unknown option: {}\nUsage: awildtxt [+line] [path|-]...", arg));
+            } else {
+                parsed.paths.push(arg);
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::Args;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    #[test]
+    fn paths_and_line() {
+        let args = parse(&["a.txt", "+42", "-", "b.txt"]).unwrap();
+        assert_eq!(args.paths, vec!["a.txt", "-", "b.txt"]);
+        assert_eq!(args.command.as_deref(), Some("42"));
+
+        assert_eq!(parse(&["+"]).unwrap().command.as_deref(), Some("$"));
+        assert_eq!(parse(&[]).unwrap(), Args::default());
+    }
+
+    #[test]
+    fn options_end_at_double_dash() {
+        assert_eq!(parse(&["--", "-x", "+1"]).unwrap().paths, vec!["-x", "+1"]);
+        assert!(parse(&["-x"]).unwrap_err().starts_with("Unknown option: -x"));
+    }
+}
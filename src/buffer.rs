@@ -1,24 +1,66 @@
+use std::io;
+
 use piece_table::PieceTable;
 
-use crate::piece_table;
+use crate::{piece_table, file::write_file, viewport::Viewport};
 
 pub struct Buffer {
     pub path: Option<String>,
     pub pt: PieceTable,
+    // Where the cursor was when another buffer was switched to
+    pub cursor_index: u32,
+    pub viewport: Viewport,
 }
 
 impl Buffer {
     pub fn new() -> Self {
         Buffer {
             path: None,
-            pt: PieceTable::new()
+            pt: PieceTable::new(),
+            cursor_index: 0,
+            viewport: Viewport::new(),
         }
     }
 
     pub fn from(path: &str, content: String) -> Self {
         Buffer {
             path: Some(path.to_owned()),
-            pt: PieceTable::init(content)
+            pt: PieceTable::init(content),
+            cursor_index: 0,
+            viewport: Viewport::new(),
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), io::Error> {
+        match &self.path {
+            Some(path) => write_file(path, &self.pt.read())?,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "Buffer has no path to save to")),
         }
+
+        self.pt.mark_saved();
+        Ok(())
+    }
+
+    pub fn save_as(&mut self, path: &str) -> Result<(), io::Error> {
+        write_file(path, &self.pt.read())?;
+        self.path = Some(path.to_owned());
+        self.pt.mark_saved();
+        Ok(())
     }
-}
\ No newline at end of file
+
+    pub fn is_modified(&self) -> bool {
+        self.pt.is_modified()
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.path.as_deref().unwrap_or("[No Name]")
+    }
+
+    /// How lines end in this buffer, going by the first line break
+    pub fn line_ending(&self) -> &'static str {
+        match self.pt.line_start(1) {
+            Some(next) if self.pt.slice(0, next - 1).ends_with('\r') => "crlf",
+            _ => "lf",
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{buffer::Buffer, cursor::Cursor};
+
+/// Owns every open buffer, there is always at least one
+pub struct BufferManager {
+    buffers: Vec<Buffer>,
+    current: usize,
+}
+
+impl BufferManager {
+    pub fn new() -> Self {
+        BufferManager {
+            buffers: vec![Buffer::new()],
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &Buffer {
+        &self.buffers[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current]
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.iter()
+    }
+
+    /// The cursor position of the current buffer is stored and the one of the new buffer restored
+    pub fn switch_to(&mut self, index: usize, cursor: &mut Cursor) {
+        if index >= self.buffers.len() {
+            return;
+        }
+
+        self.buffers[self.current].cursor_index = cursor.index;
+        self.current = index;
+        cursor.index = self.buffers[self.current].cursor_index;
+    }
+
+    pub fn next(&mut self, cursor: &mut Cursor) {
+        self.switch_to((self.current + 1) % self.buffers.len(), cursor);
+    }
+
+    pub fn previous(&mut self, cursor: &mut Cursor) {
+        self.switch_to((self.current + self.buffers.len() - 1) % self.buffers.len(), cursor);
+    }
+
+    /// Opens the buffer next to the current one, an untouched empty buffer is replaced instead
+    pub fn open(&mut self, buffer: Buffer, cursor: &mut Cursor) {
+        let current = self.current();
+        if current.path.is_none() && !current.is_modified() && current.pt.is_empty() {
+            self.buffers[self.current] = buffer;
+            cursor.index = 0;
+            return;
+        }
+
+        self.buffers.insert(self.current + 1, buffer);
+        self.switch_to(self.current + 1, cursor);
+    }
+
+    /// Closing the last buffer leaves an empty one behind
+    pub fn close_current(&mut self, cursor: &mut Cursor) {
+        self.buffers.remove(self.current);
+        if self.buffers.is_empty() {
+            self.buffers.push(Buffer::new());
+        }
+
+        self.current = self.current.min(self.buffers.len() - 1);
+        cursor.index = self.buffers[self.current].cursor_index;
+    }
+
+    pub fn first_modified(&self) -> Option<usize> {
+        self.buffers.iter().position(|b| b.is_modified())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{buffer::Buffer, buffer_manager::BufferManager, cursor::Cursor};
+
+    #[test]
+    fn open_replaces_untouched_empty_buffer() {
+        let mut buffers = BufferManager::new();
+        let mut cursor = Cursor::new((10, 20));
+
+        buffers.open(Buffer::from("a.txt", String::from("a")), &mut cursor);
+        assert_eq!(buffers.len(), 1);
+
+        buffers.open(Buffer::from("b.txt", String::from("b")), &mut cursor);
+        assert_eq!(buffers.len(), 2);
+        assert_eq!(buffers.current().path.as_deref(), Some("b.txt"));
+    }
+
+    #[test]
+    fn switching_keeps_cursor_per_buffer() {
+        let mut buffers = BufferManager::new();
+        let mut cursor = Cursor::new((10, 20));
+        buffers.open(Buffer::from("a.txt", String::from("aaaa")), &mut cursor);
+        cursor.index = 3;
+
+        buffers.open(Buffer::from("b.txt", String::from("bbbb")), &mut cursor);
+        assert_eq!(cursor.index, 0);
+        cursor.index = 1;
+
+        buffers.next(&mut cursor);
+        assert_eq!(buffers.current().path.as_deref(), Some("a.txt"));
+        assert_eq!(cursor.index, 3);
+
+        buffers.previous(&mut cursor);
+        assert_eq!(buffers.current().path.as_deref(), Some("b.txt"));
+        assert_eq!(cursor.index, 1);
+    }
+
+    #[test]
+    fn close_last_buffer_leaves_empty_buffer() {
+        let mut buffers = BufferManager::new();
+        let mut cursor = Cursor::new((10, 20));
+        buffers.open(Buffer::from("a.txt", String::from("a")), &mut cursor);
+        buffers.open(Buffer::from("b.txt", String::from("b")), &mut cursor);
+
+        buffers.close_current(&mut cursor);
+        assert_eq!(buffers.current().path.as_deref(), Some("a.txt"));
+
+        buffers.close_current(&mut cursor);
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers.current().path, None);
+    }
+}
@@ -1,107 +1,131 @@
 use sdl2::{pixels::{Color}, render::{Canvas}, video::{Window}, rect::Rect};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{motion::{self, MotionKind}, piece_table::PieceTable, viewport::{ScreenRow, Viewport}};
 
 #[derive(Clone, Debug)]
 pub struct Cursor {
     pub index: u32,
+    // Where the selection started, the selection runs from here to `index`
+    pub anchor: Option<u32>,
     pub font_size: (u32, u32),
     pub cursor_line: bool,
 }
 
 impl Cursor {
-    /// TODO: lines_diff should indicate how many lines are being moved
-    /// for now it will just indicate up or down +1 or -1
-    pub fn calc_new_index(cursor: &Cursor, content: &String, lines_diff: i32) -> Option<u32> {
-        if lines_diff != 1 && lines_diff != -1 {
-            panic!("Don't use a lines_diff that isn't 1 or -1");
-        }
+    /// Index after moving `lines_diff` lines down (or up when negative), keeping the column when the
+    /// target line is long enough
+    pub fn calc_new_index(cursor: &Cursor, pt: &PieceTable, lines_diff: i32) -> Option<u32> {
+        let (line, _) = pt.offset_to_line_col(cursor.index);
+        let column = Self::column(pt, cursor.index);
 
-        // TODO: cleanup and refactor (ideally would be combined)
-        match lines_diff {
-            n if n > 0 => Self::move_down(cursor, content),
-            n if n < 0 => Self::move_up(cursor, content),
-            _ => None,
+        let target_line = line as i64 + lines_diff as i64;
+        if target_line < 0 || target_line >= pt.line_count() as i64 {
+            return None;
         }
-    }
 
-    fn move_up(cursor: &Cursor, content: &String) -> Option<u32> {
-        let current_line_number = cursor.get_current_line_number(content);
-        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        Self::index_at_column(pt, target_line as u32, column)
+    }
 
-        if current_line_number == 0 {
-            return None;
+    /// Like `calc_new_index`, but moves over the rows of wrapped lines when wrapping is on
+    pub fn calc_visual_index(cursor: &Cursor, pt: &PieceTable, lines_diff: i32, viewport: &Viewport) -> Option<u32> {
+        if !viewport.wrap {
+            return Self::calc_new_index(cursor, pt, lines_diff);
         }
 
-        let previous_line = lines[(current_line_number - 1) as usize];
-        let line_char = cursor.get_line_char_count_until_cursor(content, current_line_number);
-        let move_size = if previous_line == "\n" {
-            line_char + (previous_line.len() as u32)
-        } else {
-            if previous_line.len() <= line_char as usize {
-                (line_char - previous_line.len() as u32) + previous_line.len() as u32 + 1
+        let width = viewport.width;
+        let mut index = cursor.index;
+        for _ in 0..lines_diff.unsigned_abs() {
+            let (line, _) = pt.offset_to_line_col(index);
+            let rows = viewport.line_rows(pt, line);
+            let column = Self::column(pt, index);
+            let row = (column / width).min(rows - 1);
+            let row_column = column - row * width;
+
+            let (target_line, target_row) = if lines_diff > 0 {
+                if row + 1 < rows {
+                    (line, row + 1)
+                } else if line + 1 < pt.line_count() {
+                    (line + 1, 0)
+                } else {
+                    return None;
+                }
+            } else if row > 0 {
+                (line, row - 1)
+            } else if line > 0 {
+                (line - 1, viewport.line_rows(pt, line - 1) - 1)
             } else {
-                previous_line.len() as u32
+                return None;
+            };
+
+            index = Self::index_at_column(pt, target_line, target_row * width + row_column)?;
+        }
+
+        Some(index)
+    }
+
+    /// Index of `column` on `line`, clamped to the end of the line
+    pub fn index_at_column(pt: &PieceTable, line: u32, column: u32) -> Option<u32> {
+        // Walk the line a grapheme at a time so the cursor never lands inside one
+        let start = pt.line_start(line)?;
+        let end = pt.line_col_to_offset(line, u32::MAX)?;
+        let text = pt.slice(start, end);
+        let mut index_column = 0;
+        for (offset, grapheme) in text.grapheme_indices(true) {
+            if index_column >= column {
+                return Some(start + offset as u32);
             }
-        };
+            index_column += grapheme.chars().count() as u32;
+        }
 
-        println!("To move up decrease index by: {move_size}");
-        Some(move_size)
+        Some(end)
     }
 
-    fn move_down(cursor: &Cursor, content: &String) -> Option<u32> {
-        let current_line_number = cursor.get_current_line_number(content);
-        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    /// Column of `index` on its line, every character takes up one column when rendered
+    pub fn column(pt: &PieceTable, index: u32) -> u32 {
+        let (line, _) = pt.offset_to_line_col(index);
+        let line_start = pt.line_start(line).unwrap_or(0);
 
-        if lines.len() <= (current_line_number + 1) as usize {
-            return None;
-        }
-        
-        let l = lines[current_line_number as usize];
-        let next_line = lines[(current_line_number + 1) as usize];
-        let move_size = if next_line == "\n" {
-            let line_chars_until_cursor = cursor.get_line_char_count_until_cursor(content, current_line_number);
-            l.len() as u32 - line_chars_until_cursor
-        } else {
-            let line_char = cursor.get_line_char_count_until_cursor(content, current_line_number);
-            if line_char >= next_line.len() as u32 {
-                let remaining_chars_len = l.len() as u32 - line_char;
-                remaining_chars_len + (next_line.len() - 1) as u32
-            } else {
-                l.len() as u32
-            }
-        };
+        pt.chunks(line_start, index).map(|c| c.chars().count() as u32).sum()
+    }
+
+    pub fn move_left(&mut self, pt: &PieceTable) {
+        self.index = pt.prev_grapheme_boundary(self.index);
+    }
 
-        println!("To move down increase index by: {move_size}");
-        Some(move_size as u32)
+    pub fn move_right(&mut self, pt: &PieceTable) {
+        self.index = pt.next_grapheme_boundary(self.index);
+    }
+
+    /// Byte range of the selection, including the character under the cursor or the whole lines
+    /// when `linewise`
+    pub fn selection(&self, pt: &PieceTable, linewise: bool) -> Option<(u32, u32)> {
+        let anchor = self.anchor?.min(pt.len());
+        let kind = if linewise { MotionKind::Linewise } else { MotionKind::Inclusive };
+        Some(motion::range(pt, anchor, self.index, kind))
     }
 
     pub fn new(font_size: (u32, u32)) -> Self {
         Cursor {
             index: 0,
+            anchor: None,
             font_size,
             cursor_line: false,
         }
     }
 
-    pub fn render(&mut self, canvas: &mut Canvas<Window>, content: &String) {
-        let mut lines = 0; 
-        let mut chars_on_line = 0;
-
-        let current_line_number = self.get_current_line_number(content);
-        
-        for (idx, line) in content.split_inclusive('\n').enumerate() {
-            if idx == current_line_number as usize {
-                break;
-            }
-            lines += 1;
-            chars_on_line += line.len() as u32;
-        }
-
-        chars_on_line = self.index - (chars_on_line as u32);
+    pub fn render(&mut self, canvas: &mut Canvas<Window>, pt: &PieceTable, viewport: &Viewport) {
+        let (row, column) = match viewport.screen_position(pt, self.index) {
+            Some(position) => position,
+            None => return,
+        };
 
-        let x = (chars_on_line * self.font_size.0) as i32;
-        let y = (lines * self.font_size.1) as i32;
+        let x = ((viewport.gutter + column) * self.font_size.0) as i32;
+        let y = (row * self.font_size.1) as i32;
 
-        let cursor_width = self.font_size.0;
+        // Cover every character of the grapheme under the cursor
+        let grapheme = pt.slice(self.index, pt.next_grapheme_boundary(self.index));
+        let cursor_width = self.font_size.0 * (grapheme.chars().filter(|c| *c != '\n').count() as u32).max(1);
         let r = Rect::new(x, y, /*w*/cursor_width, /*h*/self.font_size.1);
         let original_blend = canvas.blend_mode();
 
@@ -109,45 +133,176 @@ impl Cursor {
         canvas.set_draw_color(Color::RGBA(255, 255, 255, 100));
         canvas.fill_rect(r).unwrap();
 
-        println!("Rendering cursor\tIndex: {}\tPosition:({};{})", self.index, r.x, r.y);
-
         canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
         canvas.set_draw_color(Color::RGBA(255, 0, 0, 50));
         let canvas_size = canvas.output_size().expect("");
 
         if self.cursor_line {
-            let cursor_line = Rect::new(0, y as i32, canvas_size.0, self.font_size.1);
+            let cursor_line = Rect::new(0, y, canvas_size.0, self.font_size.1);
             canvas.fill_rect(cursor_line).unwrap();
         }
 
         canvas.set_blend_mode(original_blend);
     }
 
-    pub fn get_current_line_number(&self, content: &String) -> u32 {
-        let mut lines = 0;
-        for (i, c) in content.chars().enumerate() {
-            if i == self.index as usize{
-                return lines;
-            }
+    /// Covers the selected part of every row on screen, like the cursor line
+    pub fn render_selection(&self, canvas: &mut Canvas<Window>, pt: &PieceTable, viewport: &Viewport, rows: &[ScreenRow], linewise: bool) {
+        if let Some(selection) = self.selection(pt, linewise) {
+            self.render_ranges(canvas, pt, viewport, rows, &[selection], Color::RGBA(80, 120, 255, 90));
+        }
+    }
 
-            if c == '\n' {
-                lines += 1;
-            }
+    /// Covers byte ranges of the buffer where they are on `rows`, the rows on screen
+    pub fn render_ranges(&self, canvas: &mut Canvas<Window>, pt: &PieceTable, viewport: &Viewport, rows: &[ScreenRow], ranges: &[(u32, u32)], color: Color) {
+        let original_blend = canvas.blend_mode();
+        canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
+        canvas.set_draw_color(color);
+
+        for (row, column, columns) in Self::range_cells(pt, rows, ranges) {
+            let r = Rect::new(
+                ((viewport.gutter + column) * self.font_size.0) as i32,
+                (row * self.font_size.1) as i32,
+                columns * self.font_size.0,
+                self.font_size.1,
+            );
+            canvas.fill_rect(r).unwrap();
         }
 
-        lines
+        canvas.set_blend_mode(original_blend);
     }
 
-    fn get_line_char_count_until_cursor(&self, content: &String, current_line_number: u32) -> u32 {
-        let mut chars = 0;
-        for (idx, line) in content.split_inclusive('\n').enumerate() {
-            if idx == current_line_number as usize {
-                break;
+    /// Row, first column and number of columns that byte ranges take up on screen, per row
+    pub fn range_cells(pt: &PieceTable, rows: &[ScreenRow], ranges: &[(u32, u32)]) -> Vec<(u32, u32, u32)> {
+        let mut cells = Vec::new();
+        for &(start, end) in ranges {
+            for (row, screen_row) in rows.iter().enumerate() {
+                let row_start = start.max(screen_row.start);
+                let row_end = end.min(screen_row.end);
+                // A selected line break takes up one cell after the end of the line
+                let line_break = end > screen_row.end && start <= screen_row.end
+                    && matches!(motion::char_at(pt, screen_row.end), Some(('\n', _)));
+                if row_start > row_end || (row_start == row_end && !line_break) {
+                    continue;
+                }
+
+                let column = pt.slice(screen_row.start, row_start).chars().count() as u32;
+                let columns = pt.slice(row_start, row_end).chars().count() as u32 + line_break as u32;
+                cells.push((row as u32, column, columns));
             }
-            chars += line.len();
         }
-
-        self.index - (chars as u32)
+        cells
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::cursor::Cursor;
+    use crate::piece_table::PieceTable;
+    use crate::viewport::Viewport;
+
+    #[test]
+    fn move_right_over_multibyte_characters() {
+        let pt = PieceTable::init(String::from("\u{e9}\u{65e5}\u{1f600}"));
+        let mut cursor = Cursor::new((10, 20));
+
+        cursor.move_right(&pt);
+        assert_eq!(cursor.index, 2);
+        cursor.move_right(&pt);
+        assert_eq!(cursor.index, 5);
+        cursor.move_right(&pt);
+        assert_eq!(cursor.index, 9);
+        cursor.move_right(&pt);
+        assert_eq!(cursor.index, 9);
+
+        cursor.move_left(&pt);
+        assert_eq!(cursor.index, 5);
+    }
+
+    #[test]
+    fn selection_includes_cursor_character() {
+        let pt = PieceTable::init(String::from("abc\n\u{e9}f\nghi"));
+        let mut cursor = Cursor::new((10, 20));
+        cursor.index = 4;
+        assert_eq!(cursor.selection(&pt, false), None);
+
+        cursor.anchor = Some(1);
+        assert_eq!(cursor.selection(&pt, false), Some((1, 6)));
+        assert_eq!(cursor.selection(&pt, true), Some((0, 8)));
+
+        // Selecting backwards
+        cursor.anchor = Some(9);
+        assert_eq!(cursor.selection(&pt, false), Some((4, 10)));
+    }
+
+    #[test]
+    fn move_down_keeps_character_column() {
+        let pt = PieceTable::init(String::from("\u{65e5}\u{672c}\u{8a9e}\nabcd\n\u{e9}"));
+        let mut cursor = Cursor::new((10, 20));
+        cursor.index = 6;
+        assert_eq!(Cursor::column(&pt, cursor.index), 2);
+
+        cursor.index = Cursor::calc_new_index(&cursor, &pt, 1).unwrap();
+        assert_eq!(cursor.index, 12);
+
+        // Clamped to the end of the shorter line
+        cursor.index = Cursor::calc_new_index(&cursor, &pt, 1).unwrap();
+        assert_eq!(cursor.index, 17);
+
+        assert_eq!(Cursor::calc_new_index(&cursor, &pt, 1), None);
+    }
+
+    #[test]
+    fn moving_over_long_lines_stays_fast() {
+        let line = "\u{e9}".repeat(200_000);
+        let pt = PieceTable::init(format!("{line}\n{line}"));
+        let mut cursor = Cursor::new((10, 20));
+        cursor.index = pt.line_start(1).unwrap() - 3;
+
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            cursor.index = Cursor::calc_new_index(&cursor, &pt, 1).unwrap();
+            cursor.index = Cursor::calc_new_index(&cursor, &pt, -1).unwrap();
+            cursor.move_left(&pt);
+            cursor.move_right(&pt);
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(cursor.index, pt.line_start(1).unwrap() - 3);
+        assert!(elapsed.as_secs() < 5, "moving over long lines took {elapsed:?}");
+    }
+
+    #[test]
+    fn ranges_across_wrapped_multibyte_characters() {
+        let pt = PieceTable::init(String::from("ab\u{e9}\ncd"));
+        let mut viewport = Viewport::new();
+        viewport.resize(10, 2);
+        viewport.wrap = true;
+
+        // The first row ends right before the multibyte character
+        assert_eq!(Cursor::range_cells(&pt, &viewport.rows(&pt), &[(1, 6)]), vec![(0, 1, 1), (1, 0, 2), (2, 0, 1)]);
+    }
+
+    #[test]
+    fn move_down_over_wrapped_rows() {
+        let pt = PieceTable::init(format!("{}\nabc", "x".repeat(25)));
+        let mut viewport = Viewport::new();
+        viewport.resize(10, 10);
+        viewport.wrap = true;
+
+        let mut cursor = Cursor::new((10, 20));
+        cursor.index = 2;
+
+        cursor.index = Cursor::calc_visual_index(&cursor, &pt, 1, &viewport).unwrap();
+        assert_eq!(cursor.index, 12);
+        cursor.index = Cursor::calc_visual_index(&cursor, &pt, 1, &viewport).unwrap();
+        assert_eq!(cursor.index, 22);
+        cursor.index = Cursor::calc_visual_index(&cursor, &pt, 1, &viewport).unwrap();
+        assert_eq!(cursor.index, 28);
+        assert_eq!(Cursor::calc_visual_index(&cursor, &pt, 1, &viewport), None);
+
+        cursor.index = Cursor::calc_visual_index(&cursor, &pt, -1, &viewport).unwrap();
+        assert_eq!(cursor.index, 22);
+        cursor.index = Cursor::calc_visual_index(&cursor, &pt, -2, &viewport).unwrap();
+        assert_eq!(cursor.index, 2);
+    }
+}
@@ -0,0 +1,2361 @@
+use std::{collections::VecDeque, io::Read, path::PathBuf};
+
+use sdl2::keyboard::{Keycode, Mod};
+
+use crate::{args::Args, buffer::Buffer, buffer_manager::BufferManager, cursor::Cursor, ex::ExCommand, file::{self, read_file}, file_picker::FilePicker, keymap::{Command, Key, KeyChord, KeyMatch, Keymap}, motion::{self, Motion, MotionKind}, piece_table::PieceTable, register::{Register, Registers}, search::{self, SearchDirection}, substitute::{Replacement, Substitution}, text_object::TextObject};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilePathPrompt {
+    Open,
+    SaveAs,
+}
+
+impl FilePathPrompt {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FilePathPrompt::Open => "Open file:",
+            FilePathPrompt::SaveAs => "Save as:",
+        }
+    }
+}
+
+/// What to continue with once the unsaved changes of the buffer have been dealt with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnsavedAction {
+    Quit,
+    Close,
+}
+
+/// Anything that asks the user something and takes over the keyboard until it is answered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prompt {
+    FilePath(FilePathPrompt),
+    UnsavedChanges(UnsavedAction),
+    // Selected entry of the buffer list
+    BufferPicker(usize),
+    // The picker itself is kept in `Editor::file_picker`
+    FilePicker,
+    Search(SearchDirection),
+    // Asks whether to replace each match of `:s` with the `c` flag
+    ConfirmSubstitute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    Command,
+    Prompt(Prompt),
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Insert => "insert",
+            Mode::Visual => "visual",
+            Mode::VisualLine => "visual line",
+            Mode::Command => "command",
+            Mode::Prompt(_) => "prompt",
+        }
+    }
+
+    pub fn is_visual(&self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine)
+    }
+}
+
+// Inserted in front of a line by `>`
+const INDENT: &str = "    ";
+
+// A paste may add this much or as much as the buffer already holds, anything bigger is most likely
+// a mistyped count and would only stall the editor
+const MAX_PASTE_LENGTH: u32 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// All editor state that is changed by input, kept apart from SDL so it can be driven by tests
+pub struct Editor {
+    pub mode: Mode,
+    pub buffers: BufferManager,
+    pub cursor: Cursor,
+    // Extra cursors that text input and deletion are applied to as well, kept sorted and apart from
+    // the main cursor
+    pub cursors: Vec<Cursor>,
+    // Text typed into the command line or a file path prompt
+    pub prompt_input: PieceTable,
+    // Paths that tab completed the file path prompt to, shown until something else is typed, with
+    // the one that is in the prompt right now
+    pub completions: Vec<String>,
+    pub completion_index: Option<usize>,
+    pub keymap: Keymap,
+    // Keys typed so far of a sequence that is bound in the keymap
+    pending_keys: Vec<KeyChord>,
+    // Count typed in normal mode before a command
+    count: Option<u32>,
+    // Operator waiting for a motion, with the count typed before it
+    pending_operator: Option<(Operator, Option<u32>)>,
+    // `f`, `t` or a text object waiting for the character typed after it
+    pending_char: Option<Command>,
+    pub registers: Registers,
+    // Open while in the file picker prompt
+    pub file_picker: Option<FilePicker>,
+    // Register picked with `"` for the next operator or paste
+    pending_register: Option<char>,
+    // Set when saving a buffer without a path had to go through the save as prompt first
+    after_save_as: Option<UnsavedAction>,
+    // Shown at the bottom until the next key
+    pub message: Option<String>,
+    last_search: Option<(String, SearchDirection)>,
+    // Cursor position to go back to when a search is cancelled
+    search_origin: u32,
+    // Matches of the last search stay highlighted until escape is pressed in normal mode
+    highlight_search: bool,
+    // Matches of a substitution that still have to be confirmed, with how much the replacements so
+    // far moved them and how many were made
+    pending_replacements: VecDeque<Replacement>,
+    replacement_shift: i64,
+    replaced: u32,
+    // Commands run from the command line, oldest first, and the one shown while going through them
+    command_history: Vec<String>,
+    history_index: Option<usize>,
+    pub quit: bool,
+}
+
+impl Editor {
+    pub fn new(font_size: (u32, u32)) -> Self {
+        Editor {
+            mode: Mode::Normal,
+            buffers: BufferManager::new(),
+            cursor: Cursor::new(font_size),
+            cursors: Vec::new(),
+            prompt_input: PieceTable::new(),
+            completions: Vec::new(),
+            completion_index: None,
+            keymap: Keymap::new(),
+            pending_keys: Vec::new(),
+            count: None,
+            pending_operator: None,
+            pending_char: None,
+            registers: Registers::new(),
+            file_picker: None,
+            pending_register: None,
+            after_save_as: None,
+            message: None,
+            last_search: None,
+            search_origin: 0,
+            highlight_search: false,
+            pending_replacements: VecDeque::new(),
+            replacement_shift: 0,
+            replaced: 0,
+            command_history: Vec::new(),
+            history_index: None,
+            quit: false,
+        }
+    }
+
+    /// Whether typed text should arrive as text input events, printable keys in normal and visual
+    /// mode arrive that way too
+    pub fn wants_text_input(&self) -> bool {
+        self.pending_char.is_some() || matches!(self.mode,
+            Mode::Normal | Mode::Insert | Mode::Visual | Mode::VisualLine | Mode::Command |
+            Mode::Prompt(Prompt::FilePath(_) | Prompt::FilePicker | Prompt::Search(_)))
+    }
+
+    /// Sizes the viewport of the current buffer and scrolls it to the cursor
+    pub fn update_viewport(&mut self, height: u32, width: u32) {
+        let buffer = self.buffers.current_mut();
+        buffer.viewport.update_gutter(buffer.pt.line_count());
+        buffer.viewport.resize(height, width);
+        buffer.viewport.scroll_to_cursor(&buffer.pt, self.cursor.index);
+    }
+
+    /// What to highlight the matches of, the query as it is typed or the last search
+    pub fn search_query(&self) -> Option<String> {
+        if let Mode::Prompt(Prompt::Search(_)) = self.mode {
+            return Some(self.prompt_input.read()).filter(|query| !query.is_empty());
+        }
+
+        self.last_search.as_ref().filter(|_| self.highlight_search).map(|(query, _)| query.clone())
+    }
+
+    fn start_file_path_prompt(&mut self, prompt: FilePathPrompt) {
+        self.mode = Mode::Prompt(Prompt::FilePath(prompt));
+        self.prompt_input = PieceTable::new();
+        self.clear_completions();
+    }
+
+    /// Quits when no buffer has unsaved changes, otherwise asks about the first one
+    pub fn request_quit(&mut self) {
+        match self.buffers.first_modified() {
+            Some(index) => {
+                self.buffers.switch_to(index, &mut self.cursor);
+                self.mode = Mode::Prompt(Prompt::UnsavedChanges(UnsavedAction::Quit));
+            },
+            None => self.quit = true,
+        }
+    }
+
+    /// Closes the current buffer and, when quitting, moves on to the next buffer with unsaved changes
+    fn continue_unsaved_action(&mut self, action: UnsavedAction) {
+        self.buffers.close_current(&mut self.cursor);
+        self.mode = Mode::Normal;
+        if action == UnsavedAction::Quit {
+            self.request_quit();
+        }
+    }
+
+    pub fn handle_key(&mut self, keycode: Keycode, keymod: Mod) {
+        // The character for `f`, `t` and text objects arrives as text input
+        if self.pending_char.is_some() {
+            if keycode == Keycode::Escape {
+                self.cancel_pending();
+            }
+            return;
+        }
+
+        self.message = None;
+
+        // Extra cursors belong to the buffer they were added in
+        let buffer = self.buffers.current_index();
+
+        match self.mode {
+            Mode::Prompt(Prompt::UnsavedChanges(action)) => self.unsaved_changes_key(action, keycode),
+            Mode::Prompt(Prompt::BufferPicker(selected)) => self.buffer_picker_key(selected, keycode),
+            Mode::Prompt(Prompt::FilePath(prompt)) => self.file_path_key(prompt, keycode),
+            Mode::Prompt(Prompt::FilePicker) => self.file_picker_key(keycode),
+            Mode::Prompt(Prompt::Search(direction)) => self.search_key(direction, keycode),
+            Mode::Prompt(Prompt::ConfirmSubstitute) => self.confirm_substitute_key(keycode),
+            Mode::Command => self.command_key(keycode),
+            Mode::Normal | Mode::Insert | Mode::Visual | Mode::VisualLine => {
+                if let Some(chord) = KeyChord::from_event(keycode, keymod) {
+                    self.mapped_key(chord);
+                }
+            },
+        }
+
+        if self.buffers.current_index() != buffer {
+            self.cursors.clear();
+        }
+    }
+
+    pub fn handle_text(&mut self, text: &str) {
+        if let Some(command) = self.pending_char.take() {
+            if let Some(c) = text.chars().next() {
+                match command {
+                    Command::FindChar => self.motion(Motion::FindChar(c)),
+                    Command::TillChar => self.motion(Motion::TillChar(c)),
+                    Command::SelectRegister if Registers::is_valid(c) => self.pending_register = Some(c),
+                    Command::SelectRegister => self.cancel_pending(),
+                    _ => match TextObject::from_char(c) {
+                        Some(object) => self.text_object(object, command == Command::AroundObject),
+                        None => self.cancel_pending(),
+                    },
+                }
+            }
+            return;
+        }
+
+        if matches!(self.mode, Mode::Normal | Mode::Visual | Mode::VisualLine) {
+            let mut chars = text.chars();
+            if let Some(c) = chars.next() {
+                let buffer = self.buffers.current_index();
+                self.mapped_key(KeyChord::from_text(c));
+                if self.buffers.current_index() != buffer {
+                    self.cursors.clear();
+                }
+                // The rest may be meant for the mode the first key switched to
+                if !chars.as_str().is_empty() {
+                    self.handle_text(chars.as_str());
+                }
+            }
+            return;
+        }
+
+        match self.mode {
+            Mode::Insert => self.edit_cursors(|pt, index| {
+                if !pt.insert(text, index) {
+                    return Err(format!("Write denied ({} at index: {})", text, index));
+                }
+                Ok(index + text.len() as u32)
+            }),
+            Mode::Command => self.prompt_input.append(text),
+            Mode::Prompt(Prompt::FilePath(_)) => {
+                self.clear_completions();
+                self.prompt_input.append(text);
+            },
+            Mode::Prompt(Prompt::FilePicker) => {
+                if let Some(picker) = &mut self.file_picker {
+                    picker.push_str(text);
+                }
+            },
+            Mode::Prompt(Prompt::Search(direction)) => {
+                self.prompt_input.append(text);
+                self.incremental_search(direction);
+            },
+            _ => {},
+        }
+    }
+
+    pub fn handle_scroll(&mut self, y: i32) {
+        let buffer = self.buffers.current_mut();
+        let line_count = buffer.pt.line_count();
+        buffer.viewport.scroll(-y * 3, line_count);
+
+        // Drag the cursor along when it would scroll out of view
+        let (line, _) = buffer.pt.offset_to_line_col(self.cursor.index);
+        let target_line = buffer.viewport.clamp_line(&buffer.pt, line);
+        if let Some(index) = Cursor::calc_new_index(&self.cursor, &buffer.pt, target_line as i32 - line as i32) {
+            self.cursor.index = index;
+        }
+    }
+
+    fn unsaved_changes_key(&mut self, action: UnsavedAction, keycode: Keycode) {
+        let buffer = self.buffers.current_mut();
+        match keycode {
+            Keycode::S => {
+                if buffer.path.is_none() {
+                    self.after_save_as = Some(action);
+                    self.start_file_path_prompt(FilePathPrompt::SaveAs);
+                } else if let Err(e) = buffer.save() {
+                    self.message = Some(format!("Failed to save: {}", e));
+                    self.mode = Mode::Normal;
+                } else {
+                    self.continue_unsaved_action(action);
+                }
+            },
+            Keycode::D => self.continue_unsaved_action(action),
+            Keycode::C | Keycode::Escape => self.mode = Mode::Normal,
+            _ => {},
+        }
+    }
+
+    fn buffer_picker_key(&mut self, selected: usize, keycode: Keycode) {
+        match keycode {
+            Keycode::Up | Keycode::K => self.mode = Mode::Prompt(Prompt::BufferPicker(selected.saturating_sub(1))),
+            Keycode::Down | Keycode::J => self.mode = Mode::Prompt(Prompt::BufferPicker((selected + 1).min(self.buffers.len() - 1))),
+            Keycode::Return => {
+                self.buffers.switch_to(selected, &mut self.cursor);
+                self.mode = Mode::Normal;
+            },
+            Keycode::Escape => self.mode = Mode::Normal,
+            _ => {},
+        }
+    }
+
+    pub fn open_file_picker(&mut self, dir: PathBuf) {
+        self.file_picker = Some(FilePicker::new(dir));
+        self.mode = Mode::Prompt(Prompt::FilePicker);
+    }
+
+    fn close_file_picker(&mut self) {
+        self.file_picker = None;
+        self.mode = Mode::Normal;
+    }
+
+    fn file_picker_key(&mut self, keycode: Keycode) {
+        let picker = match &mut self.file_picker {
+            Some(picker) => picker,
+            None => return self.close_file_picker(),
+        };
+
+        match keycode {
+            Keycode::Up => picker.move_selection(-1),
+            Keycode::Down => picker.move_selection(1),
+            Keycode::PageUp => picker.move_selection(-10),
+            Keycode::PageDown => picker.move_selection(10),
+            // With nothing left to delete it goes up a directory
+            Keycode::Backspace if !picker.pop() => {
+                picker.parent_dir();
+            },
+            Keycode::Return => {
+                let entry = match picker.selected() {
+                    Some(entry) => entry.clone(),
+                    None => return,
+                };
+                let path = picker.dir.join(&entry.path);
+                if entry.is_dir {
+                    picker.change_dir(path);
+                    return;
+                }
+
+                let path = path.to_string_lossy().into_owned();
+                match read_file(&path) {
+                    Ok(content) => {
+                        self.buffers.open(Buffer::from(&path, content), &mut self.cursor);
+                        self.close_file_picker();
+                    },
+                    Err(e) => picker.error = Some(file::read_error(&entry.display(), &e)),
+                }
+            },
+            Keycode::Escape => self.close_file_picker(),
+            _ => {},
+        }
+    }
+
+    /// Removes the last grapheme of the prompt input, returns false when there was nothing to remove
+    fn prompt_backspace(&mut self) -> bool {
+        let end = self.prompt_input.len();
+        let start = self.prompt_input.prev_grapheme_boundary(end);
+        if start == end {
+            return false;
+        }
+
+        self.prompt_input.delete(start, end - start)
+    }
+
+    fn clear_completions(&mut self) {
+        self.completions.clear();
+        self.completion_index = None;
+    }
+
+    /// Completes the path in the prompt as far as it is the same for every match. Once that does
+    /// not get any further, tab goes through the matches one by one.
+    fn complete_prompt_path(&mut self) {
+        let completion = if self.completions.is_empty() {
+            let completions = file::complete_path(&self.prompt_input.read());
+            match completions.len() {
+                0 => {
+                    self.message = Some(String::from("No match"));
+                    return;
+                },
+                1 => completions[0].clone(),
+                _ => {
+                    let prefix = String::from(file::common_prefix(&completions));
+                    self.completions = completions;
+                    prefix
+                },
+            }
+        } else {
+            let index = self.completion_index.map_or(0, |i| (i + 1) % self.completions.len());
+            self.completion_index = Some(index);
+            self.completions[index].clone()
+        };
+
+        self.prompt_input = PieceTable::new();
+        self.prompt_input.append(&completion);
+    }
+
+    fn file_path_key(&mut self, prompt: FilePathPrompt, keycode: Keycode) {
+        if keycode == Keycode::Tab {
+            self.complete_prompt_path();
+            return;
+        }
+        self.clear_completions();
+
+        match keycode {
+            Keycode::Escape => {
+                self.mode = Mode::Normal;
+                self.after_save_as = None;
+            },
+            Keycode::Backspace => {
+                self.prompt_backspace();
+            },
+            Keycode::Return => {
+                let path = file::expand_home(&self.prompt_input.read());
+                match prompt {
+                    FilePathPrompt::Open => match read_file(&path) {
+                        Ok(content) => self.buffers.open(Buffer::from(&path, content), &mut self.cursor),
+                        // The prompt stays open to fix the path
+                        Err(e) => {
+                            self.message = Some(file::read_error(&path, &e));
+                            return;
+                        },
+                    },
+                    FilePathPrompt::SaveAs => {
+                        if let Err(e) = self.buffers.current_mut().save_as(&path) {
+                            self.message = Some(format!("Failed to save {}: {}", path, e));
+                            self.after_save_as = None;
+                        }
+                    },
+                }
+                self.mode = Mode::Normal;
+
+                if let Some(action) = self.after_save_as.take() {
+                    self.continue_unsaved_action(action);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn search_key(&mut self, direction: SearchDirection, keycode: Keycode) {
+        match keycode {
+            Keycode::Escape => {
+                self.mode = Mode::Normal;
+                self.cursor.index = self.search_origin;
+            },
+            Keycode::Backspace if !self.prompt_backspace() => {
+                self.mode = Mode::Normal;
+                self.cursor.index = self.search_origin;
+            },
+            Keycode::Backspace => self.incremental_search(direction),
+            Keycode::Return => {
+                let query = self.prompt_input.read();
+                self.mode = Mode::Normal;
+                self.cursor.index = self.search_origin;
+                // An empty search repeats the last one in the new direction
+                if !query.is_empty() {
+                    self.last_search = Some((query, direction));
+                } else if let Some((_, last_direction)) = self.last_search.as_mut() {
+                    *last_direction = direction;
+                }
+                self.search_next(false);
+            },
+            _ => {},
+        }
+    }
+
+    /// Moves to the first match of the query typed so far
+    fn incremental_search(&mut self, direction: SearchDirection) {
+        self.cursor.index = self.search_origin;
+        let query = self.prompt_input.read();
+        if let Some((offset, _)) = search::find(&self.buffers.current().pt, &query, self.search_origin, direction) {
+            self.cursor.index = offset;
+        }
+    }
+
+    /// Jumps to the next match of the last search, or the previous one when `reverse`. Used with an
+    /// operator it works on the text up to the match.
+    fn search_next(&mut self, reverse: bool) {
+        let (operator, count) = self.take_operator();
+        let (query, direction) = match &self.last_search {
+            Some((query, direction)) => (query.clone(), if reverse { direction.reverse() } else { *direction }),
+            None => {
+                self.message = Some(String::from("No previous search"));
+                return;
+            },
+        };
+        self.highlight_search = true;
+
+        let pt = &self.buffers.current().pt;
+        let mut target = self.cursor.index;
+        for _ in 0..count.unwrap_or(1) {
+            match search::find(pt, &query, target, direction) {
+                Some((offset, wrapped)) => {
+                    if wrapped {
+                        self.message = Some(String::from(match direction {
+                            SearchDirection::Forward => "search hit BOTTOM, continuing at TOP",
+                            SearchDirection::Backward => "search hit TOP, continuing at BOTTOM",
+                        }));
+                    }
+                    target = offset;
+                },
+                None => {
+                    self.message = Some(format!("Pattern not found: {}", query));
+                    return;
+                },
+            }
+        }
+
+        match operator {
+            Some(operator) => self.apply_operator(operator, self.cursor.index, target, MotionKind::Exclusive),
+            None => self.cursor.index = target,
+        }
+    }
+
+    fn command_key(&mut self, keycode: Keycode) {
+        match keycode {
+            // Deleting past the start of the command line leaves it, like vim
+            Keycode::Escape => self.mode = Mode::Normal,
+            Keycode::Backspace if !self.prompt_backspace() => self.mode = Mode::Normal,
+            Keycode::Return => {
+                let command = self.prompt_input.read();
+                self.mode = Mode::Normal;
+                if !command.trim().is_empty() && self.command_history.last() != Some(&command) {
+                    self.command_history.push(command.clone());
+                }
+                self.execute_command(&command);
+            },
+            Keycode::Up => {
+                let index = match self.history_index {
+                    Some(index) => index.saturating_sub(1),
+                    None if !self.command_history.is_empty() => self.command_history.len() - 1,
+                    None => return,
+                };
+                self.history_index = Some(index);
+                self.prompt_input = PieceTable::init(self.command_history[index].clone());
+            },
+            Keycode::Down => {
+                // Going past the newest command leaves an empty command line
+                self.history_index = self.history_index.map(|index| index + 1).filter(|index| *index < self.command_history.len());
+                self.prompt_input = match self.history_index {
+                    Some(index) => PieceTable::init(self.command_history[index].clone()),
+                    None => PieceTable::new(),
+                };
+            },
+            _ => {},
+        }
+    }
+
+    fn execute_command(&mut self, command: &str) {
+        if command.trim().is_empty() {
+            return;
+        }
+
+        let result = ExCommand::parse(command).and_then(|command| self.execute_ex(command));
+        if let Err(e) = result {
+            self.message = Some(e);
+        }
+    }
+
+    fn execute_ex(&mut self, command: ExCommand) -> Result<(), String> {
+        match command {
+            ExCommand::Write(path) => self.write(path.as_deref())?,
+            ExCommand::Quit { force: true } => self.quit = true,
+            ExCommand::Quit { force: false } => self.quit_unless_modified()?,
+            ExCommand::WriteQuit => {
+                self.write(None)?;
+                self.quit_unless_modified()?;
+            },
+            ExCommand::Edit(path) => self.open_path(&path)?,
+            ExCommand::NextBuffer => self.buffers.next(&mut self.cursor),
+            ExCommand::PreviousBuffer => self.buffers.previous(&mut self.cursor),
+            ExCommand::DeleteBuffer { force } => {
+                if !force && self.buffers.current().is_modified() {
+                    return Err(String::from("No write since last change (add ! to override)"));
+                }
+                self.buffers.close_current(&mut self.cursor);
+            },
+            ExCommand::Set(options) => {
+                for option in options {
+                    self.set_option(&option)?;
+                }
+            },
+            ExCommand::GotoLine(line) => {
+                let pt = &self.buffers.current().pt;
+                let last = pt.line_count() - 1;
+                let line = line.map_or(last, |line| line.saturating_sub(1).min(last));
+                self.cursor.index = motion::first_non_blank(pt, line);
+            },
+            ExCommand::Substitute(substitution) => self.substitute(substitution),
+        }
+        Ok(())
+    }
+
+    /// Saves the current buffer, to `path` when given
+    fn write(&mut self, path: Option<&str>) -> Result<(), String> {
+        let buffer = self.buffers.current_mut();
+        let result = match path {
+            Some(path) => buffer.save_as(&file::expand_home(path)),
+            None if buffer.path.is_none() => return Err(String::from("No file name")),
+            None => buffer.save(),
+        };
+        result.map_err(|e| format!("Failed to save: {}", e))?;
+
+        self.message = Some(format!("\"{}\" written", buffer.display_name()));
+        Ok(())
+    }
+
+    /// Quits unless a buffer has unsaved changes, that buffer is shown instead
+    fn quit_unless_modified(&mut self) -> Result<(), String> {
+        match self.buffers.first_modified() {
+            Some(index) => {
+                self.buffers.switch_to(index, &mut self.cursor);
+                Err(format!("No write since last change for buffer \"{}\" (add ! to override)", self.buffers.current().display_name()))
+            },
+            None => {
+                self.quit = true;
+                Ok(())
+            },
+        }
+    }
+
+    /// Opens a file in a new buffer, a file that does not exist yet is created when it is saved
+    fn open_path(&mut self, path: &str) -> Result<(), String> {
+        let path = &file::expand_home(path);
+        let content = match read_file(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.message = Some(format!("\"{}\" [New]", path));
+                String::new()
+            },
+            Err(e) => return Err(file::read_error(path, &e)),
+        };
+
+        self.buffers.open(Buffer::from(path, content), &mut self.cursor);
+        Ok(())
+    }
+
+    /// Opens the files from the command line, `-` reads a buffer from `stdin`. The first of them is
+    /// shown and the `+` command is run in it.
+    pub fn open_args(&mut self, args: &Args, mut stdin: impl Read) {
+        let mut first = None;
+        for path in &args.paths {
+            let opened = if path == "-" {
+                let mut content = String::new();
+                match stdin.read_to_string(&mut content) {
+                    Ok(_) => {
+                        let mut buffer = Buffer::new();
+                        buffer.pt = PieceTable::init(content);
+                        // Quitting would lose the piped text otherwise
+                        buffer.pt.mark_unsaved();
+                        self.buffers.open(buffer, &mut self.cursor);
+                        Ok(())
+                    },
+                    Err(e) => Err(format!("Failed to read stdin: {}", e)),
+                }
+            } else {
+                self.open_path(path)
+            };
+
+            match opened {
+                Ok(()) => {
+                    first.get_or_insert(self.buffers.current_index());
+                },
+                Err(e) => self.message = Some(e),
+            }
+        }
+
+        if let Some(first) = first {
+            self.buffers.switch_to(first, &mut self.cursor);
+        }
+        if let Some(command) = &args.command {
+            self.execute_command(command);
+        }
+    }
+
+    /// `name` or `noname` for switches, `name=value` for everything else
+    fn set_option(&mut self, option: &str) -> Result<(), String> {
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        };
+        let (name, enable) = match option.strip_prefix("no") {
+            Some(name) if value.is_none() => (name, false),
+            _ => (name, true),
+        };
+        let switch = || match value {
+            None => Ok(enable),
+            Some("true" | "on") => Ok(true),
+            Some("false" | "off") => Ok(false),
+            Some(_) => Err(format!("Invalid argument: {}", option)),
+        };
+
+        let viewport = &mut self.buffers.current_mut().viewport;
+        match name {
+            "wrap" => viewport.wrap = switch()?,
+            "number" | "nu" => viewport.number = switch()?,
+            "relativenumber" | "rnu" => viewport.relative_number = switch()?,
+            "cursorline" => self.cursor.cursor_line = switch()?,
+            "scrolloff" => {
+                viewport.scroll_margin = value.and_then(|value| value.parse().ok())
+                    .ok_or_else(|| format!("Invalid argument: {}", option))?;
+            },
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+        Ok(())
+    }
+
+    /// The match that waits for confirmation, moved to where it is now
+    pub fn pending_replacement(&self) -> Option<Replacement> {
+        let replacement = self.pending_replacements.front()?;
+        Some(Replacement {
+            start: (replacement.start as i64 + self.replacement_shift) as u32,
+            end: (replacement.end as i64 + self.replacement_shift) as u32,
+            text: replacement.text.clone(),
+        })
+    }
+
+    /// Replaces the matches on the line of the cursor, or in the whole buffer, as a single undo step
+    fn substitute(&mut self, substitution: Substitution) {
+        let pt = &mut self.buffers.current_mut().pt;
+        let (line, _) = pt.offset_to_line_col(self.cursor.index);
+        let (first, last) = if substitution.whole_buffer { (0, pt.line_count() - 1) } else { (line, line) };
+
+        let replacements = substitution.replacements(pt, first, last);
+        if replacements.is_empty() {
+            self.message = Some(format!("Pattern not found: {}", substitution.pattern.as_str()));
+            return;
+        }
+
+        pt.begin_undo_group();
+        self.pending_replacements = replacements.into();
+        self.replacement_shift = 0;
+        self.replaced = 0;
+
+        if substitution.confirm {
+            self.mode = Mode::Prompt(Prompt::ConfirmSubstitute);
+            self.cursor.index = self.pending_replacements[0].start;
+        } else {
+            while !self.pending_replacements.is_empty() {
+                self.replace_next();
+            }
+            self.finish_substitute();
+        }
+    }
+
+    fn replace_next(&mut self) {
+        let replacement = match self.pending_replacement() {
+            Some(replacement) => replacement,
+            None => return,
+        };
+        self.pending_replacements.pop_front();
+
+        let pt = &mut self.buffers.current_mut().pt;
+        let length = replacement.end - replacement.start;
+        if (length > 0 && !pt.delete(replacement.start, length)) || !pt.insert(&replacement.text, replacement.start) {
+            self.message = Some(format!("Failed to replace range ({}..{})", replacement.start, replacement.end));
+            return;
+        }
+
+        self.replacement_shift += replacement.text.len() as i64 - length as i64;
+        self.replaced += 1;
+        self.cursor.index = replacement.start;
+    }
+
+    fn finish_substitute(&mut self) {
+        self.pending_replacements.clear();
+        self.mode = Mode::Normal;
+
+        let pt = &mut self.buffers.current_mut().pt;
+        pt.end_undo_group();
+        if self.replaced > 0 {
+            let (line, _) = pt.offset_to_line_col(self.cursor.index);
+            self.cursor.index = pt.line_start(line).unwrap_or(0);
+            let plural = if self.replaced == 1 { "" } else { "s" };
+            self.message = Some(format!("{} substitution{}", self.replaced, plural));
+        }
+    }
+
+    /// (y)es, (n)o, (a)ll remaining, (l)ast one or (q)uit
+    fn confirm_substitute_key(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::Y => self.replace_next(),
+            Keycode::N => {
+                self.pending_replacements.pop_front();
+            },
+            Keycode::A => {
+                while !self.pending_replacements.is_empty() {
+                    self.replace_next();
+                }
+            },
+            Keycode::L => {
+                self.replace_next();
+                self.pending_replacements.clear();
+            },
+            Keycode::Q | Keycode::Escape => self.pending_replacements.clear(),
+            _ => return,
+        }
+
+        match self.pending_replacement() {
+            Some(replacement) => self.cursor.index = replacement.start,
+            None => self.finish_substitute(),
+        }
+    }
+
+    fn cancel_pending(&mut self) {
+        self.count = None;
+        self.pending_operator = None;
+        self.pending_char = None;
+        self.pending_register = None;
+    }
+
+    /// Digits typed in normal and visual mode before a command, a 0 on its own is a motion
+    fn count_key(&mut self, chord: KeyChord) -> bool {
+        if self.mode == Mode::Insert || !self.pending_keys.is_empty() || chord.ctrl || chord.alt || chord.shift {
+            return false;
+        }
+
+        let digit = match chord.key {
+            Key::Char('0') if self.count.is_none() => return false,
+            Key::Char(c @ '0'..='9') => c as u32 - '0' as u32,
+            _ => return false,
+        };
+        self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+        true
+    }
+
+    /// Collects keys until they match a binding of the current mode
+    fn mapped_key(&mut self, chord: KeyChord) {
+        // Holding a modifier on its own should not break off a sequence
+        if matches!(chord.key, Key::Code(
+            Keycode::LShift | Keycode::RShift | Keycode::LCtrl | Keycode::RCtrl |
+            Keycode::LAlt | Keycode::RAlt | Keycode::LGui | Keycode::RGui)) {
+            return;
+        }
+
+        if self.count_key(chord) {
+            return;
+        }
+
+        self.pending_keys.push(chord);
+        match self.keymap.lookup(self.mode, self.pending_operator.is_some(), &self.pending_keys) {
+            KeyMatch::Command(command) => {
+                self.pending_keys.clear();
+                self.execute(command);
+            },
+            KeyMatch::Pending => {},
+            KeyMatch::None => {
+                // The key that broke off a sequence can still start a new one
+                let retry = self.pending_keys.len() > 1;
+                self.pending_keys.clear();
+                if retry {
+                    self.mapped_key(chord);
+                } else {
+                    self.cancel_pending();
+                }
+            },
+        }
+    }
+
+    fn execute(&mut self, command: Command) {
+        let buffer = self.buffers.current_mut();
+        // Consecutive text input is grouped into a single undo step until a command is used
+        buffer.pt.end_undo_group();
+
+        let index = self.cursor.index;
+        match command {
+            Command::MoveLeft => self.motion(Motion::Left),
+            Command::MoveRight => self.motion(Motion::Right),
+            Command::MoveUp => self.motion(Motion::Up),
+            Command::MoveDown => self.motion(Motion::Down),
+            Command::WordForward => self.motion(Motion::WordForward),
+            Command::WordBackward => self.motion(Motion::WordBackward),
+            Command::WordEnd => self.motion(Motion::WordEnd),
+            Command::LineStart => self.motion(Motion::LineStart),
+            Command::LineEnd => self.motion(Motion::LineEnd),
+            Command::FirstLine => self.motion(Motion::FirstLine),
+            Command::LastLine => self.motion(Motion::LastLine),
+            Command::FindChar | Command::TillChar | Command::InnerObject | Command::AroundObject | Command::SelectRegister => {
+                self.pending_char = Some(command);
+            },
+            Command::Delete => self.operator(Operator::Delete),
+            Command::Change => self.operator(Operator::Change),
+            Command::Yank => self.operator(Operator::Yank),
+            Command::PasteAfter => self.paste(false),
+            Command::PasteBefore => self.paste(true),
+            Command::CopyToClipboard => {
+                self.pending_register = Some('+');
+                match self.take_selection() {
+                    Some((anchor, index, kind)) => self.apply_operator(Operator::Yank, anchor, index, kind),
+                    // Without a selection the whole line is copied
+                    None => self.apply_operator(Operator::Yank, index, index, MotionKind::Linewise),
+                }
+            },
+            Command::PasteFromClipboard => {
+                if let Some(register) = self.registers.get('+') {
+                    self.edit_cursors(|pt, index| {
+                        if !pt.insert(&register.text, index) {
+                            return Err(format!("Failed to paste at index: {}", index));
+                        }
+                        Ok(index + register.text.len() as u32)
+                    });
+                    self.buffers.current_mut().pt.end_undo_group();
+                }
+            },
+            Command::Indent => self.indent(true),
+            Command::Outdent => self.indent(false),
+            Command::ToggleCase | Command::Lowercase | Command::Uppercase => self.change_case(command),
+            Command::SearchForward | Command::SearchBackward => {
+                let direction = if command == Command::SearchForward { SearchDirection::Forward } else { SearchDirection::Backward };
+                self.cancel_pending();
+                self.mode = Mode::Prompt(Prompt::Search(direction));
+                self.prompt_input = PieceTable::new();
+                self.search_origin = index;
+            },
+            Command::NextMatch => self.search_next(false),
+            Command::PreviousMatch => self.search_next(true),
+            Command::PageUp => self.page(-1),
+            Command::PageDown => self.page(1),
+            Command::InsertMode => self.mode = Mode::Insert,
+            Command::VisualMode => self.visual(Mode::Visual),
+            Command::VisualLineMode => self.visual(Mode::VisualLine),
+            Command::NormalMode => {
+                // Escape in normal mode goes back to a single cursor
+                if self.mode == Mode::Normal && self.pending_operator.is_none() {
+                    self.cursors.clear();
+                    self.highlight_search = false;
+                }
+                self.cancel_pending();
+                self.mode = Mode::Normal;
+                self.cursor.anchor = None;
+            },
+            Command::OpenCommandLine => {
+                self.mode = Mode::Command;
+                self.prompt_input = PieceTable::new();
+                self.history_index = None;
+            },
+            Command::DeleteChar => {
+                // Like `dl`, but never joins lines
+                let line_end = Motion::LineEnd.apply(&buffer.pt, index, None, false).unwrap_or(index);
+                let mut end = index;
+                for _ in 0..self.count.take().unwrap_or(1) {
+                    if end >= line_end {
+                        break;
+                    }
+                    end = buffer.pt.next_grapheme_boundary(end).min(line_end);
+                }
+                if end != index {
+                    self.apply_operator(Operator::Delete, index, end, MotionKind::Exclusive);
+                }
+            },
+            Command::DeleteForward => {
+                self.edit_cursors(|pt, index| {
+                    let end = pt.next_grapheme_boundary(index);
+                    if end != index && !pt.delete(index, end - index) {
+                        return Err(format!("Failed to delete character ({})", index));
+                    }
+                    Ok(index)
+                });
+                self.buffers.current_mut().pt.end_undo_group();
+            },
+            Command::DeleteBackward => {
+                self.edit_cursors(|pt, index| {
+                    let start = pt.prev_grapheme_boundary(index);
+                    if start != index && !pt.delete(start, index - start) {
+                        return Err(format!("Failed to delete character ({})", start));
+                    }
+                    Ok(start)
+                });
+                self.buffers.current_mut().pt.end_undo_group();
+            },
+            Command::Newline => {
+                self.edit_cursors(|pt, index| {
+                    if !pt.insert("\n", index) {
+                        return Err(format!("Failed to insert newline at index: {}", index));
+                    }
+                    Ok(index + 1)
+                });
+                self.buffers.current_mut().pt.end_undo_group();
+            },
+            // The extra cursors can't follow what an undo changed, so only the main cursor is kept
+            Command::Undo => {
+                self.cursors.clear();
+                if let Some(offset) = buffer.pt.undo() {
+                    self.cursor.index = offset.min(buffer.pt.len());
+                }
+            },
+            Command::Redo => {
+                self.cursors.clear();
+                if let Some(offset) = buffer.pt.redo() {
+                    self.cursor.index = offset.min(buffer.pt.len());
+                }
+            },
+            Command::OpenFile => self.start_file_path_prompt(FilePathPrompt::Open),
+            Command::FilePicker => match std::env::current_dir() {
+                Ok(dir) => self.open_file_picker(dir),
+                Err(e) => self.message = Some(format!("Failed to read the working directory: {}", e)),
+            },
+            Command::Save => {
+                if buffer.path.is_some() {
+                    if let Err(e) = self.write(None) {
+                        self.message = Some(e);
+                    }
+                } else {
+                    self.start_file_path_prompt(FilePathPrompt::SaveAs);
+                }
+            },
+            Command::CloseBuffer => {
+                if buffer.is_modified() {
+                    self.mode = Mode::Prompt(Prompt::UnsavedChanges(UnsavedAction::Close));
+                } else {
+                    self.buffers.close_current(&mut self.cursor);
+                }
+            },
+            Command::NextBuffer => self.buffers.next(&mut self.cursor),
+            Command::PreviousBuffer => self.buffers.previous(&mut self.cursor),
+            Command::BufferList => self.mode = Mode::Prompt(Prompt::BufferPicker(self.buffers.current_index())),
+            Command::ToggleWrap => buffer.viewport.wrap = !buffer.viewport.wrap,
+            Command::AddCursorAtNextMatch => self.add_cursor_at_next_match(),
+            Command::AddCursorsToLines => self.add_cursors_to_lines(),
+        }
+    }
+
+    /// Runs `edit` at every cursor as a single undo step. The edits are made front to back, so each
+    /// cursor only has to be moved by what was inserted or deleted before it. A cursor stays where it
+    /// is when its edit fails.
+    fn edit_cursors(&mut self, mut edit: impl FnMut(&mut PieceTable, u32) -> Result<u32, String>) {
+        let pt = &mut self.buffers.current_mut().pt;
+        pt.begin_undo_group();
+
+        let mut cursors: Vec<&mut Cursor> = std::iter::once(&mut self.cursor).chain(self.cursors.iter_mut()).collect();
+        cursors.sort_by_key(|cursor| cursor.index);
+
+        let mut shift: i64 = 0;
+        for cursor in cursors {
+            let index = ((cursor.index as i64 + shift).max(0) as u32).min(pt.len());
+            let len = pt.len();
+            match edit(pt, index) {
+                Ok(new_index) => cursor.index = new_index,
+                Err(e) => self.message = Some(e),
+            }
+            shift += pt.len() as i64 - len as i64;
+        }
+
+        self.merge_cursors();
+    }
+
+    /// Drops extra cursors that ended up on the same spot as another cursor
+    fn merge_cursors(&mut self) {
+        let index = self.cursor.index;
+        self.cursors.sort_by_key(|cursor| cursor.index);
+        self.cursors.dedup_by_key(|cursor| cursor.index);
+        self.cursors.retain(|cursor| cursor.index != index);
+    }
+
+    fn add_cursor(&mut self, index: u32) {
+        self.cursors.push(Cursor { index, anchor: None, ..self.cursor.clone() });
+        self.merge_cursors();
+    }
+
+    /// Adds a cursor on the next whole word match of the word under the cursor, after the cursor
+    /// that was added last
+    fn add_cursor_at_next_match(&mut self) {
+        let pt = &self.buffers.current().pt;
+        let (start, end) = match TextObject::Word.range(pt, self.cursor.index, None, false) {
+            Some(range) => range,
+            None => return,
+        };
+        let word = pt.slice(start, end);
+        if word.trim().is_empty() {
+            return;
+        }
+
+        let class = motion::class(word.chars().next().unwrap_or(' '));
+        let is_word_boundary = |at: u32| {
+            let before = pt.slice(pt.prev_grapheme_boundary(at), at).chars().next_back();
+            let end = at + word.len() as u32;
+            let after = pt.slice(end, pt.next_grapheme_boundary(end)).chars().next();
+            [before, after].iter().flatten().all(|c| motion::class(*c) != class)
+        };
+
+        // Keep the cursors at the same spot inside the word
+        let offset = self.cursor.index - start;
+        let last = self.cursors.last().map_or(self.cursor.index, |cursor| cursor.index.max(self.cursor.index));
+        let from = (last - offset.min(last) + word.len() as u32).min(pt.len());
+
+        // `find` looks after the character at its offset, matches inside other words are skipped
+        // until the search comes back around to the first one
+        let mut search_from = pt.prev_grapheme_boundary(from);
+        let mut first = None;
+        while let Some((found, _)) = search::find(pt, &word, search_from, SearchDirection::Forward) {
+            if is_word_boundary(found) {
+                self.add_cursor(found + offset);
+                return;
+            }
+            if first == Some(found) {
+                return;
+            }
+            first.get_or_insert(found);
+            search_from = found;
+        }
+    }
+
+    /// Puts a cursor on every selected line at the column of the cursor
+    fn add_cursors_to_lines(&mut self) {
+        let (anchor, index, _) = match self.take_selection() {
+            Some(selection) => selection,
+            None => return,
+        };
+
+        let pt = &self.buffers.current().pt;
+        let column = Cursor::column(pt, index);
+        let (line, _) = pt.offset_to_line_col(index);
+        let (anchor_line, _) = pt.offset_to_line_col(anchor);
+        let indices: Vec<u32> = (line.min(anchor_line)..=line.max(anchor_line))
+            .filter(|l| *l != line)
+            .filter_map(|l| Cursor::index_at_column(pt, l, column))
+            .collect();
+
+        for index in indices {
+            self.add_cursor(index);
+        }
+    }
+
+    /// Starts selecting, switches between selecting characters and lines, or stops selecting when
+    /// `mode` is already active
+    fn visual(&mut self, mode: Mode) {
+        if self.mode == mode {
+            self.mode = Mode::Normal;
+            self.cursor.anchor = None;
+            return;
+        }
+
+        if !self.mode.is_visual() {
+            self.cursor.anchor = Some(self.cursor.index);
+        }
+        self.mode = mode;
+    }
+
+    /// Ends visual mode and returns the selection, with the kind it has to be treated as
+    fn take_selection(&mut self) -> Option<(u32, u32, MotionKind)> {
+        if !self.mode.is_visual() {
+            return None;
+        }
+
+        let kind = if self.mode == Mode::VisualLine { MotionKind::Linewise } else { MotionKind::Inclusive };
+        let anchor = self.cursor.anchor.take().unwrap_or(self.cursor.index);
+        self.mode = Mode::Normal;
+        self.count = None;
+        Some((anchor.min(self.buffers.current().pt.len()), self.cursor.index, kind))
+    }
+
+    fn operator(&mut self, operator: Operator) {
+        // Operators in visual mode work on the selection right away
+        if let Some((anchor, index, kind)) = self.take_selection() {
+            self.apply_operator(operator, anchor, index, kind);
+            return;
+        }
+
+        match self.pending_operator.take() {
+            // Doubled operators like `dd` and `yy` work on whole lines
+            Some((pending, count)) if pending == operator => {
+                let lines = count.unwrap_or(1).saturating_mul(self.count.take().unwrap_or(1));
+                let pt = &self.buffers.current().pt;
+                let (line, _) = pt.offset_to_line_col(self.cursor.index);
+                let last_line = line.saturating_add(lines - 1).min(pt.line_count() - 1);
+                let to = pt.line_start(last_line).unwrap_or(0);
+                self.apply_operator(operator, self.cursor.index, to, MotionKind::Linewise);
+            },
+            _ => self.pending_operator = Some((operator, self.count.take())),
+        }
+    }
+
+    /// The pending operator and the count for what follows it, counts before and after the operator multiply
+    fn take_operator(&mut self) -> (Option<Operator>, Option<u32>) {
+        match self.pending_operator.take() {
+            Some((operator, operator_count)) => {
+                let count = match (operator_count, self.count.take()) {
+                    (None, None) => None,
+                    (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+                };
+                (Some(operator), count)
+            },
+            None => (None, self.count.take()),
+        }
+    }
+
+    fn text_object(&mut self, object: TextObject, around: bool) {
+        let (operator, count) = self.take_operator();
+        let pt = &self.buffers.current().pt;
+        let range = object.range(pt, self.cursor.index, count, around);
+
+        let operator = match operator {
+            Some(operator) => operator,
+            // In visual mode the object is selected instead
+            None => {
+                if let Some((start, end)) = range.filter(|(start, end)| self.mode.is_visual() && start < end) {
+                    self.cursor.anchor = Some(start);
+                    self.cursor.index = pt.prev_grapheme_boundary(end);
+                    if object.is_linewise() {
+                        self.mode = Mode::VisualLine;
+                    }
+                }
+                return;
+            },
+        };
+
+        if let Some((start, end)) = range {
+            if object.is_linewise() {
+                self.apply_operator(operator, start, end.saturating_sub(1).max(start), MotionKind::Linewise);
+            } else {
+                self.apply_operator(operator, start, end, MotionKind::Exclusive);
+            }
+        }
+    }
+
+    /// Moves the cursor, or applies the pending operator to the text moved over
+    fn motion(&mut self, motion: Motion) {
+        let (operator, count) = self.take_operator();
+
+        let pt = &self.buffers.current().pt;
+        let index = self.cursor.index;
+        let operator = match operator {
+            Some(operator) => operator,
+            None => {
+                // More lines than fit in an i32 are more than any buffer has
+                let lines = i32::try_from(count.unwrap_or(1)).unwrap_or(i32::MAX);
+                match motion {
+                    Motion::Left | Motion::Right => for _ in 0..count.unwrap_or(1) {
+                        let before = self.cursor.index;
+                        if motion == Motion::Left { self.cursor.move_left(pt) } else { self.cursor.move_right(pt) }
+                        // Nothing left to move over at the start or end of the buffer
+                        if self.cursor.index == before {
+                            break;
+                        }
+                    },
+                    // Plain up and down movement goes over wrapped rows
+                    Motion::Up => self.move_lines(-lines),
+                    Motion::Down => self.move_lines(lines),
+                    _ => {
+                        if let Some(target) = motion.apply(pt, index, count, false) {
+                            self.cursor.index = target;
+                        }
+                    },
+                }
+                return;
+            },
+        };
+
+        let on_word = pt.slice(index, pt.next_grapheme_boundary(index)).chars().next().is_some_and(|c| !c.is_whitespace());
+        if operator == Operator::Change && motion == Motion::WordForward && on_word {
+            let end = motion::change_word_end(pt, index, count);
+            self.apply_operator(operator, index, end, MotionKind::Inclusive);
+        } else if let Some(target) = motion.apply(pt, index, count, true) {
+            self.apply_operator(operator, index, target, motion.kind());
+        }
+    }
+
+    fn apply_operator(&mut self, operator: Operator, from: u32, to: u32, kind: MotionKind) {
+        let buffer = self.buffers.current_mut();
+        let linewise = kind == MotionKind::Linewise;
+        let (mut start, mut end) = motion::range(&buffer.pt, from, to, kind);
+
+        let mut text = buffer.pt.slice(start, end);
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        if let Err(e) = self.registers.store(self.pending_register.take(), Register { text, linewise }, operator == Operator::Yank) {
+            self.message = Some(e);
+        }
+
+        match operator {
+            Operator::Yank => {
+                self.cursor.index = if linewise { from.min(to) } else { start };
+                return;
+            },
+            Operator::Change => {
+                // Changed lines are emptied but stay around to type in
+                if linewise && buffer.pt.slice(start, end).ends_with('\n') {
+                    end -= 1;
+                }
+                buffer.pt.begin_undo_group();
+                self.mode = Mode::Insert;
+            },
+            Operator::Delete => {
+                // Deleting the last lines takes the line break before them instead of after
+                if linewise && end == buffer.pt.len() && start > 0 && !buffer.pt.slice(start, end).ends_with('\n') {
+                    start -= 1;
+                }
+            },
+        }
+
+        if start != end && !buffer.pt.delete(start, end - start) {
+            self.message = Some(format!("Failed to delete range ({}..{})", start, end));
+            return;
+        }
+
+        self.cursor.index = start.min(buffer.pt.len());
+        if linewise && operator == Operator::Delete {
+            let (line, _) = buffer.pt.offset_to_line_col(self.cursor.index);
+            self.cursor.index = buffer.pt.line_start(line).unwrap_or(0);
+        }
+    }
+
+    /// Puts the picked register after or before the cursor, lines go below or above the current line
+    fn paste(&mut self, before: bool) {
+        let register = match self.registers.get(self.pending_register.take().unwrap_or('"')) {
+            Some(register) => register,
+            None => return,
+        };
+        let count = self.count.take().unwrap_or(1);
+        let pt = &mut self.buffers.current_mut().pt;
+
+        // Offsets are u32, so the buffer can't grow past u32::MAX bytes either
+        let room = pt.len().max(MAX_PASTE_LENGTH).min(u32::MAX - pt.len());
+        let length = (register.text.len() as u64).checked_mul(count as u64);
+        if length.is_none_or(|length| length > room as u64) {
+            self.message = Some(format!("Not enough room to paste {} times", count));
+            return;
+        }
+        let mut text = register.text.repeat(count as usize);
+
+        let index = self.cursor.index;
+        let (at, cursor) = if register.linewise {
+            let (line, _) = pt.offset_to_line_col(index);
+            match pt.line_start(if before { line } else { line + 1 }) {
+                Some(at) => (at, at),
+                // Below a last line without a line break, which then has to come first
+                None => {
+                    text.pop();
+                    text.insert(0, '\n');
+                    (pt.len(), pt.len() + 1)
+                },
+            }
+        } else {
+            let at_line_end = matches!(motion::char_at(pt, index), None | Some(('\n', _)));
+            let at = if before || at_line_end { index } else { pt.next_grapheme_boundary(index) };
+            // The cursor ends on the last pasted character
+            (at, at + text.len() as u32)
+        };
+
+        pt.begin_undo_group();
+        if !pt.insert(&text, at) {
+            self.message = Some(format!("Failed to paste at index: {}", at));
+        }
+        pt.end_undo_group();
+
+        self.cursor.index = if register.linewise { cursor } else { pt.prev_grapheme_boundary(cursor) };
+    }
+
+    /// Indents or outdents the selected lines, or `count` lines from the cursor
+    fn indent(&mut self, indent: bool) {
+        let (from, to) = match self.take_selection() {
+            Some((anchor, index, _)) => (anchor.min(index), anchor.max(index)),
+            None => (self.cursor.index, self.cursor.index),
+        };
+
+        let buffer = self.buffers.current_mut();
+        let (first_line, _) = buffer.pt.offset_to_line_col(from);
+        let (mut last_line, _) = buffer.pt.offset_to_line_col(to);
+        if let Some(count) = self.count.take() {
+            last_line = first_line.saturating_add(count - 1).min(buffer.pt.line_count() - 1);
+        }
+
+        buffer.pt.begin_undo_group();
+        for line in first_line..=last_line {
+            let start = buffer.pt.line_start(line).unwrap_or(0);
+            let text = buffer.pt.slice(start, start + buffer.pt.line_len(line).unwrap_or(0));
+            if indent {
+                // Empty lines are left without trailing whitespace
+                if !text.is_empty() {
+                    buffer.pt.insert(INDENT, start);
+                }
+            } else {
+                let width = if text.starts_with('\t') {
+                    1
+                } else {
+                    text.len() - text.trim_start_matches(' ').len()
+                };
+                let width = width.min(INDENT.len()) as u32;
+                if width > 0 {
+                    buffer.pt.delete(start, width);
+                }
+            }
+        }
+        buffer.pt.end_undo_group();
+
+        self.cursor.index = motion::first_non_blank(&buffer.pt, first_line);
+    }
+
+    /// Changes the case of the selection, or of `count` characters from the cursor in normal mode
+    fn change_case(&mut self, command: Command) {
+        let selection = self.take_selection();
+        let buffer = self.buffers.current_mut();
+        let index = self.cursor.index;
+        let (start, end) = match selection {
+            Some((anchor, index, kind)) => motion::range(&buffer.pt, anchor, index, kind),
+            None => {
+                let line_end = Motion::LineEnd.apply(&buffer.pt, index, None, false).unwrap_or(index);
+                let mut end = index;
+                for _ in 0..self.count.take().unwrap_or(1) {
+                    if end >= line_end {
+                        break;
+                    }
+                    end = buffer.pt.next_grapheme_boundary(end).min(line_end);
+                }
+                (index, end)
+            },
+        };
+
+        let text = buffer.pt.slice(start, end);
+        let changed: String = text.chars().flat_map(|c| {
+            let upper = match command {
+                Command::Lowercase => false,
+                Command::Uppercase => true,
+                _ => !c.is_uppercase(),
+            };
+            if upper { c.to_uppercase().collect::<Vec<_>>() } else { c.to_lowercase().collect() }
+        }).collect();
+
+        if changed != text {
+            buffer.pt.begin_undo_group();
+            if !buffer.pt.delete(start, end - start) || !buffer.pt.insert(&changed, start) {
+                self.message = Some(format!("Failed to change case of range ({}..{})", start, end));
+            }
+            buffer.pt.end_undo_group();
+        }
+
+        // `~` steps over the characters it changed
+        self.cursor.index = if selection.is_some() { start } else { start + changed.len() as u32 };
+    }
+
+    fn move_lines(&mut self, lines: i32) {
+        let buffer = self.buffers.current();
+        if let Some(index) = Cursor::calc_visual_index(&self.cursor, &buffer.pt, lines, &buffer.viewport) {
+            self.cursor.index = index;
+        }
+    }
+
+    fn page(&mut self, direction: i32) {
+        let buffer = self.buffers.current_mut();
+        let line_count = buffer.pt.line_count();
+        let page = buffer.viewport.height as i32 * direction;
+        buffer.viewport.scroll(page, line_count);
+
+        let (line, _) = buffer.pt.offset_to_line_col(self.cursor.index);
+        let target_line = (line as i64 + page as i64).clamp(0, line_count as i64 - 1);
+        if let Some(index) = Cursor::calc_new_index(&self.cursor, &buffer.pt, (target_line - line as i64) as i32) {
+            self.cursor.index = index;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::{Keycode, Mod};
+
+    use crate::args::Args;
+    use crate::editor::{Editor, FilePathPrompt, Mode, Prompt, UnsavedAction};
+    use crate::register::Register;
+    use crate::search::SearchDirection;
+    use crate::piece_table::PieceTable;
+    use crate::test_util::TempDir;
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor::new((10, 20));
+        editor.buffers.current_mut().pt = PieceTable::init(String::from(text));
+        editor.update_viewport(10, 80);
+        editor
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.buffers.current().pt.read()
+    }
+
+    /// Presses a key the way SDL reports it, text input only arrives while it is enabled
+    fn press(editor: &mut Editor, keycode: Keycode, keymod: Mod, text: Option<&str>) {
+        let text_input = editor.wants_text_input();
+        editor.handle_key(keycode, keymod);
+        if let (true, Some(text)) = (text_input, text) {
+            editor.handle_text(text);
+        }
+    }
+
+    fn type_keys(editor: &mut Editor, keys: &str) {
+        for c in keys.chars() {
+            let text = c.to_string();
+            match c {
+                ':' => press(editor, Keycode::Semicolon, Mod::LSHIFTMOD, Some(&text)),
+                '$' => press(editor, Keycode::Num4, Mod::LSHIFTMOD, Some(&text)),
+                ',' => press(editor, Keycode::Comma, Mod::NOMOD, Some(&text)),
+                '"' => press(editor, Keycode::Quote, Mod::LSHIFTMOD, Some(&text)),
+                '(' => press(editor, Keycode::Num9, Mod::LSHIFTMOD, Some(&text)),
+                '<' => press(editor, Keycode::Comma, Mod::LSHIFTMOD, Some(&text)),
+                '>' => press(editor, Keycode::Period, Mod::LSHIFTMOD, Some(&text)),
+                '?' => press(editor, Keycode::Slash, Mod::LSHIFTMOD, Some(&text)),
+                '~' => press(editor, Keycode::Backquote, Mod::LSHIFTMOD, Some(&text)),
+                c if c.is_ascii_uppercase() => {
+                    let keycode = Keycode::from_i32(c.to_ascii_lowercase() as i32).unwrap();
+                    press(editor, keycode, Mod::LSHIFTMOD, Some(&text));
+                },
+                _ => press(editor, Keycode::from_i32(c as i32).unwrap(), Mod::NOMOD, Some(&text)),
+            }
+        }
+    }
+
+    fn key(editor: &mut Editor, keycode: Keycode) {
+        press(editor, keycode, Mod::NOMOD, None);
+    }
+
+    #[test]
+    fn insert_mode_types_mode_keys() {
+        let mut editor = editor("");
+
+        type_keys(&mut editor, "ihix");
+        assert_eq!(editor.mode, Mode::Insert);
+        assert_eq!(text(&editor), "hix");
+        assert_eq!(editor.cursor.index, 3);
+
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn normal_mode_keys_do_not_insert() {
+        let mut editor = editor("abc\ndef");
+
+        type_keys(&mut editor, "ljx");
+        assert_eq!(text(&editor), "abc\ndf");
+        assert_eq!(editor.cursor.index, 5);
+
+        type_keys(&mut editor, "kh");
+        assert_eq!(editor.cursor.index, 0);
+    }
+
+    #[test]
+    fn keys_follow_the_keyboard_layout() {
+        let mut editor = editor("abc def");
+
+        // A German layout types `$` with shift+4 and `:` with shift+period
+        press(&mut editor, Keycode::Num4, Mod::LSHIFTMOD, Some("$"));
+        assert_eq!(editor.cursor.index, 7);
+        press(&mut editor, Keycode::Period, Mod::LSHIFTMOD, Some(":"));
+        assert_eq!(editor.mode, Mode::Command);
+        key(&mut editor, Keycode::Escape);
+
+        // A French layout has its own key for `$` and needs shift for the digits
+        type_keys(&mut editor, "0");
+        press(&mut editor, Keycode::Dollar, Mod::NOMOD, Some("$"));
+        assert_eq!(editor.cursor.index, 7);
+        press(&mut editor, Keycode::Ampersand, Mod::LSHIFTMOD, Some("2"));
+        type_keys(&mut editor, "h");
+        assert_eq!(editor.cursor.index, 5);
+    }
+
+    #[test]
+    fn undo_typed_text_in_one_step() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, "lixy");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "axybc");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "abc");
+        assert_eq!(editor.cursor.index, 1);
+    }
+
+    #[test]
+    fn backspace_deletes_only_in_insert_mode() {
+        let mut editor = editor("abc");
+        editor.cursor.index = 2;
+
+        key(&mut editor, Keycode::Backspace);
+        assert_eq!(text(&editor), "abc");
+        assert_eq!(editor.cursor.index, 1);
+
+        type_keys(&mut editor, "i");
+        key(&mut editor, Keycode::Backspace);
+        assert_eq!(text(&editor), "bc");
+        assert_eq!(editor.cursor.index, 0);
+    }
+
+    #[test]
+    fn command_line_keeps_text_out_of_buffer() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, ":ix");
+        assert_eq!(editor.mode, Mode::Command);
+        assert_eq!(editor.prompt_input.read(), "ix");
+        assert_eq!(text(&editor), "abc");
+
+        key(&mut editor, Keycode::Backspace);
+        key(&mut editor, Keycode::Backspace);
+        assert_eq!(editor.mode, Mode::Command);
+        key(&mut editor, Keycode::Backspace);
+        assert_eq!(editor.mode, Mode::Normal);
+
+        type_keys(&mut editor, ":q");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn visual_mode_moves_and_leaves() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, "vl");
+        assert_eq!(editor.mode, Mode::Visual);
+        assert_eq!(editor.cursor.index, 1);
+        assert_eq!(editor.cursor.anchor, Some(0));
+
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.cursor.anchor, None);
+        assert_eq!(text(&editor), "abc");
+    }
+
+    #[test]
+    fn operators_on_selection() {
+        let mut editor = editor("one two\nthree\nfour");
+
+        type_keys(&mut editor, "wvey");
+        assert_eq!(editor.registers.get('"'), Some(Register { text: String::from("two"), linewise: false }));
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.cursor.index, 4);
+
+        // Selecting backwards still includes the character under the cursor
+        type_keys(&mut editor, "vhd");
+        assert_eq!(text(&editor), "onewo\nthree\nfour");
+
+        type_keys(&mut editor, "jVd");
+        assert_eq!(text(&editor), "onewo\nfour");
+        assert_eq!(editor.registers.get('"'), Some(Register { text: String::from("three\n"), linewise: true }));
+
+        type_keys(&mut editor, "viwcx");
+        assert_eq!(editor.mode, Mode::Insert);
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "onewo\nx");
+
+        // `v` and `V` switch between selecting characters and lines
+        type_keys(&mut editor, "ggvVj");
+        assert_eq!(editor.mode, Mode::VisualLine);
+        type_keys(&mut editor, "v");
+        assert_eq!(editor.mode, Mode::Visual);
+        type_keys(&mut editor, "v");
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn indent_and_case_of_selection() {
+        let mut editor = editor("ab\n\ncd\n  ef");
+
+        type_keys(&mut editor, "Vjj>");
+        assert_eq!(text(&editor), "    ab\n\n    cd\n  ef");
+        assert_eq!(editor.cursor.index, 4);
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "ab\n\ncd\n  ef");
+
+        type_keys(&mut editor, "G<<");
+        assert_eq!(text(&editor), "ab\n\ncd\nef");
+
+        type_keys(&mut editor, "ggvjjU");
+        assert_eq!(text(&editor), "AB\n\nCd\nef");
+
+        type_keys(&mut editor, "vlu");
+        assert_eq!(text(&editor), "ab\n\nCd\nef");
+
+        // `~` toggles and moves on in normal mode
+        press(&mut editor, Keycode::Backquote, Mod::LSHIFTMOD, Some("~"));
+        press(&mut editor, Keycode::Backquote, Mod::LSHIFTMOD, Some("~"));
+        assert_eq!(text(&editor), "AB\n\nCd\nef");
+        assert_eq!(editor.cursor.index, 2);
+    }
+
+    #[test]
+    fn quit_asks_about_unsaved_changes() {
+        let mut editor = editor("abc");
+        type_keys(&mut editor, "x");
+
+        editor.request_quit();
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::UnsavedChanges(UnsavedAction::Quit)));
+        assert!(!editor.quit);
+
+        type_keys(&mut editor, "c");
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(text(&editor), "bc");
+
+        editor.request_quit();
+        type_keys(&mut editor, "d");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn counts_repeat_motions_and_commands() {
+        let mut editor = editor("abcdef\n1\n2\n3\n4");
+
+        type_keys(&mut editor, "3x");
+        assert_eq!(text(&editor), "def\n1\n2\n3\n4");
+        type_keys(&mut editor, "10x");
+        assert_eq!(text(&editor), "\n1\n2\n3\n4");
+
+        type_keys(&mut editor, "3j");
+        assert_eq!(editor.cursor.index, 5);
+        type_keys(&mut editor, "gg");
+        assert_eq!(editor.cursor.index, 0);
+        type_keys(&mut editor, "G");
+        assert_eq!(editor.cursor.index, 7);
+        type_keys(&mut editor, "2G");
+        assert_eq!(editor.cursor.index, 1);
+
+        // Keys that are not digits never start a count, even when their keycode ends in one
+        key(&mut editor, Keycode::CapsLock);
+        type_keys(&mut editor, "dd");
+        assert_eq!(text(&editor), "\n2\n3\n4");
+
+        // Huge counts stop at the last line instead of overflowing
+        type_keys(&mut editor, "G9999999999>>gg99999d99999d");
+        assert_eq!(text(&editor), "");
+    }
+
+    #[test]
+    fn huge_counts_stop_at_the_buffer_edges() {
+        let mut editor = editor("one two\nthree\nfour");
+        editor.cursor.index = 8;
+
+        type_keys(&mut editor, "2147483648k");
+        assert_eq!(editor.cursor.index, 8);
+        type_keys(&mut editor, "3000000000j");
+        assert_eq!(editor.cursor.index, 8);
+
+        let start = std::time::Instant::now();
+        type_keys(&mut editor, "4294967295l");
+        assert_eq!(editor.cursor.index, 18);
+        type_keys(&mut editor, "4294967295h4294967295w");
+        assert_eq!(editor.cursor.index, 18);
+        type_keys(&mut editor, "4294967295b4294967295e");
+        assert_eq!(editor.cursor.index, 17);
+        type_keys(&mut editor, "0c4294967295w");
+        assert_eq!(text(&editor), "one two\nthree\n");
+        key(&mut editor, Keycode::Escape);
+        type_keys(&mut editor, "gg4294967295~");
+        assert_eq!(text(&editor), "ONE TWO\nthree\n");
+        assert!(start.elapsed().as_secs() < 5, "huge counts took {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn operators_with_motions() {
+        let mut editor = editor("one two three\nfour");
+
+        type_keys(&mut editor, "dw");
+        assert_eq!(text(&editor), "two three\nfour");
+
+        type_keys(&mut editor, "wdw");
+        assert_eq!(text(&editor), "two \nfour");
+
+        type_keys(&mut editor, "0d$");
+        assert_eq!(text(&editor), "\nfour");
+        assert_eq!(editor.registers.get('"').unwrap().text, "two ");
+    }
+
+    #[test]
+    fn delete_to_character() {
+        let mut editor = editor("a, b, c");
+
+        type_keys(&mut editor, "d2f,");
+        assert_eq!(text(&editor), " c");
+        type_keys(&mut editor, "u");
+        type_keys(&mut editor, "dt,");
+        assert_eq!(text(&editor), ", b, c");
+    }
+
+    #[test]
+    fn change_enters_insert_mode_as_one_undo_step() {
+        let mut editor = editor("foo bar");
+
+        type_keys(&mut editor, "cwxy");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "xy bar");
+
+        type_keys(&mut editor, "w2c$z");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "xy z");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "xy bar");
+    }
+
+    #[test]
+    fn linewise_operators() {
+        let mut editor = editor("1\n2\n3\n4\n5");
+
+        type_keys(&mut editor, "jdd");
+        assert_eq!(text(&editor), "1\n3\n4\n5");
+        assert_eq!(editor.cursor.index, 2);
+
+        type_keys(&mut editor, "2yy");
+        assert_eq!(editor.registers.get('"'), Some(Register { text: String::from("3\n4\n"), linewise: true }));
+
+        type_keys(&mut editor, "Gdk");
+        assert_eq!(text(&editor), "1\n3");
+        assert_eq!(editor.cursor.index, 2);
+
+        type_keys(&mut editor, "ggccx");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "x\n3");
+    }
+
+    #[test]
+    fn operators_on_text_objects() {
+        let mut editor = editor("call(a, \"b c\")\n\nnext");
+
+        type_keys(&mut editor, "fbdi\"");
+        assert_eq!(text(&editor), "call(a, \"\")\n\nnext");
+
+        type_keys(&mut editor, "ci(x");
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(text(&editor), "call(x)\n\nnext");
+
+        type_keys(&mut editor, "0yiw");
+        assert_eq!(editor.registers.get('"').unwrap().text, "call");
+
+        type_keys(&mut editor, "dap");
+        assert_eq!(text(&editor), "next");
+
+        // `i` without an operator still enters insert mode
+        type_keys(&mut editor, "i");
+        assert_eq!(editor.mode, Mode::Insert);
+    }
+
+    #[test]
+    fn paste_after_and_before() {
+        let mut editor = editor("one two\nthree");
+
+        type_keys(&mut editor, "yyjp");
+        assert_eq!(text(&editor), "one two\nthree\none two");
+        assert_eq!(editor.cursor.index, 14);
+
+        type_keys(&mut editor, "2P");
+        assert_eq!(text(&editor), "one two\nthree\none two\none two\none two");
+
+        type_keys(&mut editor, "ggywP");
+        assert_eq!(text(&editor), "one one two\nthree\none two\none two\none two");
+        assert_eq!(editor.cursor.index, 3);
+
+        // Undone in a single step
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "one two\nthree\none two\none two\none two");
+    }
+
+    #[test]
+    fn paste_that_does_not_fit_is_refused() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, "yl4294967295p");
+        assert_eq!(text(&editor), "abc");
+        assert_eq!(editor.message.as_deref(), Some("Not enough room to paste 4294967295 times"));
+        assert_eq!(editor.cursor.index, 0);
+
+        // Fits in the offsets but is far bigger than the buffer
+        type_keys(&mut editor, "100000000p");
+        assert_eq!(text(&editor), "abc");
+        assert_eq!(editor.message.as_deref(), Some("Not enough room to paste 100000000 times"));
+
+        type_keys(&mut editor, "3p");
+        assert_eq!(text(&editor), "aaaabc");
+    }
+
+    #[test]
+    fn paste_after_multibyte_character() {
+        let mut editor = editor("\u{e9}\u{65e5}\n");
+
+        type_keys(&mut editor, "ylp");
+        assert_eq!(text(&editor), "\u{e9}\u{e9}\u{65e5}\n");
+        assert_eq!(editor.cursor.index, 2);
+
+        type_keys(&mut editor, "$p");
+        assert_eq!(text(&editor), "\u{e9}\u{e9}\u{65e5}\u{e9}\n");
+        assert_eq!(editor.cursor.index, 7);
+    }
+
+    #[test]
+    fn named_and_black_hole_registers() {
+        let mut editor = editor("abc def");
+
+        type_keys(&mut editor, "\"ayiww\"_dw");
+        assert_eq!(text(&editor), "abc ");
+        assert_eq!(editor.registers.get('"').unwrap().text, "abc");
+
+        type_keys(&mut editor, "0x\"ap");
+        assert_eq!(text(&editor), "babcc ");
+        assert_eq!(editor.registers.get('"').unwrap().text, "a");
+    }
+
+    #[test]
+    fn clipboard_in_insert_mode() {
+        let mut editor = editor("copy\n");
+
+        type_keys(&mut editor, "\"+yiw");
+        assert_eq!(editor.registers.get('+').unwrap().text, "copy");
+
+        type_keys(&mut editor, "ji");
+        press(&mut editor, Keycode::V, Mod::LCTRLMOD, None);
+        press(&mut editor, Keycode::V, Mod::LCTRLMOD, None);
+        assert_eq!(text(&editor), "copy\ncopycopy");
+        assert_eq!(editor.cursor.index, 13);
+
+        press(&mut editor, Keycode::C, Mod::LCTRLMOD, None);
+        assert_eq!(editor.registers.get('+'), Some(Register { text: String::from("copycopy\n"), linewise: true }));
+    }
+
+    #[test]
+    fn incremental_search_and_repeat() {
+        let mut editor = editor("one two\ntwo one\ntwo");
+
+        type_keys(&mut editor, "/tw");
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::Search(SearchDirection::Forward)));
+        assert_eq!(editor.cursor.index, 4);
+        assert_eq!(editor.search_query().as_deref(), Some("tw"));
+        type_keys(&mut editor, "x");
+        assert_eq!(editor.cursor.index, 0);
+
+        // Escape goes back to where the search started
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.search_query(), None);
+
+        type_keys(&mut editor, "/two");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(editor.cursor.index, 4);
+        assert_eq!(editor.search_query().as_deref(), Some("two"));
+
+        type_keys(&mut editor, "2n");
+        assert_eq!(editor.cursor.index, 16);
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor.index, 4);
+        assert_eq!(editor.message.as_deref(), Some("search hit BOTTOM, continuing at TOP"));
+        type_keys(&mut editor, "N");
+        assert_eq!(editor.cursor.index, 16);
+        assert_eq!(editor.message.as_deref(), Some("search hit TOP, continuing at BOTTOM"));
+
+        type_keys(&mut editor, "?one");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.cursor.index, 12);
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor.index, 0);
+
+        // With an operator the search is a motion up to the match
+        type_keys(&mut editor, "dN");
+        assert_eq!(text(&editor), "one\ntwo");
+
+        type_keys(&mut editor, "/three");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.message.as_deref(), Some("Pattern not found: three"));
+
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.search_query(), None);
+    }
+
+    #[test]
+    fn substitute_as_one_undo_step() {
+        let mut editor = editor("a=1 b=2\nc=3");
+
+        type_keys(&mut editor, ":s/(\\w)=(\\d)/\\2:\\1/");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(text(&editor), "1:a b=2\nc=3");
+
+        type_keys(&mut editor, ":%s/=/ is /g");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(text(&editor), "1:a b is 2\nc is 3");
+        assert_eq!(editor.message.as_deref(), Some("2 substitutions"));
+        assert_eq!(editor.cursor.index, 11);
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "1:a b=2\nc=3");
+
+        type_keys(&mut editor, ":s/x/y/");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.message.as_deref(), Some("Pattern not found: x"));
+    }
+
+    #[test]
+    fn substitute_with_confirmation() {
+        let mut editor = editor("aa\nA\naa");
+
+        type_keys(&mut editor, ":%s/a/bc/gci");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::ConfirmSubstitute));
+        assert_eq!(editor.pending_replacement().map(|r| (r.start, r.end)), Some((0, 1)));
+
+        key(&mut editor, Keycode::Y);
+        assert_eq!(editor.pending_replacement().map(|r| (r.start, r.end)), Some((2, 3)));
+        key(&mut editor, Keycode::N);
+        assert_eq!(editor.cursor.index, 4);
+        key(&mut editor, Keycode::Y);
+        assert_eq!(text(&editor), "bca\nbc\naa");
+        key(&mut editor, Keycode::L);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(text(&editor), "bca\nbc\nbca");
+
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "aa\nA\naa");
+
+        type_keys(&mut editor, ":%s/a/b/gc");
+        key(&mut editor, Keycode::Return);
+        key(&mut editor, Keycode::A);
+        assert_eq!(text(&editor), "bb\nA\nbb");
+    }
+
+    fn run(editor: &mut Editor, command: &str) {
+        type_keys(editor, ":");
+        editor.handle_text(command);
+        key(editor, Keycode::Return);
+    }
+
+    #[test]
+    fn ex_commands() {
+        let mut editor = editor("one\n  two\nthree");
+
+        run(&mut editor, "2");
+        assert_eq!(editor.cursor.index, 6);
+        run(&mut editor, "$");
+        assert_eq!(editor.cursor.index, 10);
+        run(&mut editor, "99");
+        assert_eq!(editor.cursor.index, 10);
+
+        run(&mut editor, "set wrap scrolloff=1");
+        assert!(editor.buffers.current().viewport.wrap);
+        assert_eq!(editor.buffers.current().viewport.scroll_margin, 1);
+        run(&mut editor, "set nowrap");
+        assert!(!editor.buffers.current().viewport.wrap);
+        run(&mut editor, "set nonu rnu");
+        editor.update_viewport(10, 80);
+        assert!(!editor.buffers.current().viewport.number && editor.buffers.current().viewport.relative_number);
+        assert_eq!(editor.buffers.current().viewport.width, 76);
+        run(&mut editor, "set nornu");
+        editor.update_viewport(10, 80);
+        assert_eq!(editor.buffers.current().viewport.width, 80);
+        run(&mut editor, "set wrap=maybe");
+        assert_eq!(editor.message.as_deref(), Some("Invalid argument: wrap=maybe"));
+        run(&mut editor, "set colors");
+        assert_eq!(editor.message.as_deref(), Some("Unknown option: colors"));
+
+        run(&mut editor, "frobnicate");
+        assert_eq!(editor.message.as_deref(), Some("Not an editor command: frobnicate"));
+        run(&mut editor, "w");
+        assert_eq!(editor.message.as_deref(), Some("No file name"));
+    }
+
+    #[test]
+    fn quit_and_close_keep_unsaved_changes() {
+        let mut editor = editor("abc");
+        type_keys(&mut editor, "x");
+
+        run(&mut editor, "q");
+        assert!(!editor.quit);
+        assert_eq!(editor.message.as_deref(), Some("No write since last change for buffer \"[No Name]\" (add ! to override)"));
+
+        run(&mut editor, "bd");
+        assert_eq!(text(&editor), "bc");
+        run(&mut editor, "bd!");
+        assert_eq!(text(&editor), "");
+
+        type_keys(&mut editor, "ix");
+        key(&mut editor, Keycode::Escape);
+        run(&mut editor, "q!");
+        assert!(editor.quit);
+    }
+
+    #[test]
+    fn command_history() {
+        let mut editor = editor("abc");
+        run(&mut editor, "set wrap");
+        run(&mut editor, "1");
+        run(&mut editor, "1");
+
+        type_keys(&mut editor, ":");
+        key(&mut editor, Keycode::Up);
+        assert_eq!(editor.prompt_input.read(), "1");
+        key(&mut editor, Keycode::Up);
+        assert_eq!(editor.prompt_input.read(), "set wrap");
+        key(&mut editor, Keycode::Up);
+        assert_eq!(editor.prompt_input.read(), "set wrap");
+        key(&mut editor, Keycode::Down);
+        assert_eq!(editor.prompt_input.read(), "1");
+        key(&mut editor, Keycode::Down);
+        assert_eq!(editor.prompt_input.read(), "");
+    }
+
+    fn cursor_indices(editor: &Editor) -> Vec<u32> {
+        std::iter::once(editor.cursor.index).chain(editor.cursors.iter().map(|c| c.index)).collect()
+    }
+
+    #[test]
+    fn typing_at_every_cursor() {
+        let mut editor = editor("foo bar\nfoo.foobar\nfoo");
+
+        press(&mut editor, Keycode::N, Mod::LCTRLMOD, None);
+        press(&mut editor, Keycode::N, Mod::LCTRLMOD, None);
+        // `foobar` is not a whole word match, the search wraps around to the main cursor
+        press(&mut editor, Keycode::N, Mod::LCTRLMOD, None);
+        assert_eq!(cursor_indices(&editor), vec![0, 8, 19]);
+
+        type_keys(&mut editor, "ixy");
+        assert_eq!(text(&editor), "xyfoo bar\nxyfoo.foobar\nxyfoo");
+        assert_eq!(cursor_indices(&editor), vec![2, 12, 25]);
+
+        key(&mut editor, Keycode::Backspace);
+        key(&mut editor, Keycode::Delete);
+        assert_eq!(text(&editor), "xoo bar\nxoo.foobar\nxoo");
+
+        // The delete at every cursor is undone in one step
+        key(&mut editor, Keycode::Escape);
+        type_keys(&mut editor, "u");
+        assert_eq!(text(&editor), "xfoo bar\nxfoo.foobar\nxfoo");
+        assert!(editor.cursors.is_empty());
+    }
+
+    #[test]
+    fn cursor_at_next_match_across_pieces() {
+        let mut editor = editor("caf\u{e9} \u{e9}caf\u{e9} x");
+        editor.buffers.current_mut().pt.insert("caf", 14);
+        editor.buffers.current_mut().pt.insert("\u{e9} ", 17);
+        assert_eq!(text(&editor), "caf\u{e9} \u{e9}caf\u{e9} caf\u{e9} x");
+
+        // The match inside the word starting with an accent is skipped
+        press(&mut editor, Keycode::N, Mod::LCTRLMOD, None);
+        assert_eq!(cursor_indices(&editor), vec![0, 14]);
+    }
+
+    #[test]
+    fn cursors_on_selected_lines() {
+        let mut editor = editor("abc\nd\nefg");
+
+        type_keys(&mut editor, "lvjj");
+        press(&mut editor, Keycode::N, Mod::LCTRLMOD, None);
+        assert_eq!(editor.mode, Mode::Normal);
+        // Short lines get a cursor at their end
+        assert_eq!(cursor_indices(&editor), vec![7, 1, 5]);
+
+        type_keys(&mut editor, "i");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(text(&editor), "a\nbc\nd\n\ne\nfg");
+
+        // Escape in normal mode leaves only the main cursor
+        key(&mut editor, Keycode::Escape);
+        assert_eq!(editor.cursors.len(), 2);
+        key(&mut editor, Keycode::Escape);
+        assert!(editor.cursors.is_empty());
+    }
+
+    #[test]
+    fn escape_cancels_pending_operator() {
+        let mut editor = editor("abc");
+
+        type_keys(&mut editor, "d");
+        key(&mut editor, Keycode::Escape);
+        type_keys(&mut editor, "l");
+        assert_eq!(text(&editor), "abc");
+        assert_eq!(editor.cursor.index, 1);
+    }
+
+    #[test]
+    fn rebound_keys_and_sequences() {
+        let mut editor = editor("abc\ndef");
+        assert!(editor.keymap.apply_config("normal x = move_down\nnormal z z = delete_char").is_empty());
+
+        type_keys(&mut editor, "x");
+        assert_eq!(editor.cursor.index, 4);
+        assert_eq!(text(&editor), "abc\ndef");
+
+        type_keys(&mut editor, "z");
+        assert_eq!(text(&editor), "abc\ndef");
+        type_keys(&mut editor, "z");
+        assert_eq!(text(&editor), "abc\nef");
+
+        // A broken off sequence does not swallow the next key
+        type_keys(&mut editor, "zk");
+        assert_eq!(editor.cursor.index, 0);
+    }
+
+    #[test]
+    fn right_ctrl_opens_file_prompt() {
+        let mut editor = editor("");
+
+        press(&mut editor, Keycode::O, Mod::RCTRLMOD, None);
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::FilePath(FilePathPrompt::Open)));
+    }
+
+    #[test]
+    fn open_prompt_completes_paths_and_reports_errors() {
+        let dir = TempDir::new("editor-complete");
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("docs").join("alpha.txt"), "alpha").unwrap();
+        std::fs::write(dir.join("docs").join("beta.txt"), "beta").unwrap();
+        let dir_input = format!("{}/", dir.to_str().unwrap());
+
+        let mut editor = editor("abc");
+        press(&mut editor, Keycode::O, Mod::LCTRLMOD, None);
+        editor.handle_text(&format!("{}d", dir_input));
+        key(&mut editor, Keycode::Tab);
+        assert_eq!(editor.prompt_input.read(), format!("{}docs/", dir_input));
+
+        // Nothing in common, so the first tab shows the matches and the next ones go through them
+        key(&mut editor, Keycode::Tab);
+        assert_eq!(editor.completions.len(), 2);
+        assert_eq!(editor.completion_index, None);
+        key(&mut editor, Keycode::Tab);
+        key(&mut editor, Keycode::Tab);
+        assert_eq!(editor.prompt_input.read(), format!("{}docs/beta.txt", dir_input));
+
+        key(&mut editor, Keycode::Backspace);
+        assert!(editor.completions.is_empty());
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.message, Some(format!("{}docs/beta.tx: no such file", dir_input)));
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::FilePath(FilePathPrompt::Open)));
+
+        type_keys(&mut editor, "t");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert_eq!(text(&editor), "beta");
+    }
+
+    #[test]
+    fn save_reports_the_written_file() {
+        let dir = TempDir::new("editor-save");
+        let path = dir.join("saved.txt");
+        let path = path.to_str().unwrap();
+
+        let mut editor = editor("abc");
+        editor.buffers.current_mut().path = Some(String::from(path));
+        press(&mut editor, Keycode::S, Mod::LCTRLMOD, None);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "abc");
+        assert_eq!(editor.message, Some(format!("\"{}\" written", path)));
+        assert!(!editor.buffers.current().is_modified());
+    }
+
+    #[test]
+    fn open_command_line_arguments() {
+        let dir = TempDir::new("editor-args");
+        let existing = dir.join("existing.txt");
+        std::fs::write(&existing, "one\n  two\nthree").unwrap();
+        let new = dir.join("new.txt");
+
+        let mut editor = editor("");
+        let paths = vec![existing.to_str().unwrap(), "-", new.to_str().unwrap()];
+        let args = Args::parse(paths.into_iter().chain(["+2"]).map(String::from)).unwrap();
+        editor.open_args(&args, "piped".as_bytes());
+
+        // The empty start buffer was replaced
+        assert_eq!(editor.buffers.len(), 3);
+        assert_eq!(editor.buffers.current_index(), 0);
+        assert_eq!(text(&editor), "one\n  two\nthree");
+        assert_eq!(editor.cursor.index, 6);
+
+        editor.buffers.next(&mut editor.cursor);
+        assert_eq!(text(&editor), "piped");
+        assert_eq!(editor.buffers.current().path, None);
+        assert!(editor.buffers.current().is_modified());
+        editor.buffers.next(&mut editor.cursor);
+        assert_eq!(editor.buffers.current().path.as_deref(), new.to_str());
+        assert!(!new.exists());
+    }
+
+    #[test]
+    fn buffer_picker_takes_over_keys() {
+        let mut editor = editor("abc");
+
+        press(&mut editor, Keycode::B, Mod::RCTRLMOD, None);
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::BufferPicker(0)));
+
+        type_keys(&mut editor, "x");
+        assert_eq!(text(&editor), "abc");
+
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn file_picker_opens_files_and_reports_errors() {
+        let dir = TempDir::new("editor-picker");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub").join("notes.txt"), "notes").unwrap();
+        std::fs::write(dir.join("binary"), [0xff, 0xfe]).unwrap();
+
+        let mut editor = editor("abc");
+        editor.open_file_picker(dir.to_path_buf());
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::FilePicker));
+
+        type_keys(&mut editor, "bin");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Prompt(Prompt::FilePicker));
+        assert_eq!(editor.file_picker.as_ref().unwrap().error.as_deref(), Some("binary is not valid UTF-8"));
+
+        // Into the directory, then back out of it with backspace on an empty query
+        for _ in 0..3 {
+            key(&mut editor, Keycode::Backspace);
+        }
+        type_keys(&mut editor, "sub");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.file_picker.as_ref().unwrap().dir, dir.join("sub"));
+        key(&mut editor, Keycode::Backspace);
+        assert_eq!(editor.file_picker.as_ref().unwrap().dir, *dir);
+
+        type_keys(&mut editor, "notes");
+        key(&mut editor, Keycode::Return);
+        assert_eq!(editor.mode, Mode::Normal);
+        assert!(editor.file_picker.is_none());
+        assert_eq!(text(&editor), "notes");
+        assert_eq!(editor.buffers.len(), 2);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::substitute::Substitution;
+
+/// A command typed on the command line after `:`
+#[derive(Debug)]
+pub enum ExCommand {
+    // `:w` and `:w path`
+    Write(Option<String>),
+    // `:q` and `:q!`, forced quitting drops unsaved changes
+    Quit { force: bool },
+    WriteQuit,
+    Edit(String),
+    NextBuffer,
+    PreviousBuffer,
+    DeleteBuffer { force: bool },
+    // `:set name`, `:set noname` and `:set name=value`
+    Set(Vec<String>),
+    // Line number counted from 1, `$` is the last line
+    GotoLine(Option<u32>),
+    Substitute(Substitution),
+}
+
+impl ExCommand {
+    pub fn parse(command: &str) -> Result<Self, String> {
+        let command = command.trim();
+        if let Some(substitution) = Substitution::parse(command) {
+            return substitution.map(ExCommand::Substitute);
+        }
+
+        if command == "$" {
+            return Ok(ExCommand::GotoLine(None));
+        }
+        if let Ok(line) = command.parse::<u32>() {
+            return Ok(ExCommand::GotoLine(Some(line)));
+        }
+
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim()).filter(|a| !a.is_empty())),
+            None => (command, None),
+        };
+        let (name, force) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        match (name, force, argument) {
+            ("w" | "write", false, path) => Ok(ExCommand::Write(path.map(String::from))),
+            ("q" | "quit", force, None) => Ok(ExCommand::Quit { force }),
+            ("wq" | "x", false, None) => Ok(ExCommand::WriteQuit),
+            ("e" | "edit", false, Some(path)) => Ok(ExCommand::Edit(String::from(path))),
+            ("e" | "edit", false, None) => Err(String::from("No file name")),
+            ("bn" | "bnext", false, None) => Ok(ExCommand::NextBuffer),
+            ("bp" | "bprevious", false, None) => Ok(ExCommand::PreviousBuffer),
+            ("bd" | "bdelete", force, None) => Ok(ExCommand::DeleteBuffer { force }),
+            ("set", false, Some(options)) => Ok(ExCommand::Set(options.split_whitespace().map(String::from).collect())),
+            ("set", false, None) => Err(String::from("Argument required")),
+            _ => Err(format!("Not an editor command: {}", command)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ex::ExCommand;
+
+    fn parse(command: &str) -> ExCommand {
+        ExCommand::parse(command).unwrap()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert!(matches!(parse("w"), ExCommand::Write(None)));
+        assert!(matches!(parse("w  notes.txt "), ExCommand::Write(Some(path)) if path == "notes.txt"));
+        assert!(matches!(parse("q!"), ExCommand::Quit { force: true }));
+        assert!(matches!(parse("wq"), ExCommand::WriteQuit));
+        assert!(matches!(parse("e src/main.rs"), ExCommand::Edit(path) if path == "src/main.rs"));
+        assert!(matches!(parse("bd"), ExCommand::DeleteBuffer { force: false }));
+        assert!(matches!(parse("set nowrap scrolloff=5"), ExCommand::Set(options) if options == ["nowrap", "scrolloff=5"]));
+        assert!(matches!(parse("42"), ExCommand::GotoLine(Some(42))));
+        assert!(matches!(parse("$"), ExCommand::GotoLine(None)));
+        assert!(matches!(parse("%s/a/b/g"), ExCommand::Substitute(_)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(ExCommand::parse("frobnicate").unwrap_err(), "Not an editor command: frobnicate");
+        assert_eq!(ExCommand::parse("q now").unwrap_err(), "Not an editor command: q now");
+        assert_eq!(ExCommand::parse("e").unwrap_err(), "No file name");
+        assert_eq!(ExCommand::parse("s/a/b/z").unwrap_err(), "Unknown substitute flag: z");
+    }
+}
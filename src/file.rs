@@ -1,7 +1,186 @@
-use std::{io, fs};
-
-// TODO: This needs a real file picker eventually
+use std::{io::{self, Write}, fs, path::Path};
 
 pub fn read_file(path: &str) -> Result<String, io::Error> {
     fs::read_to_string(path)
 }
+
+/// Says why `read_file` failed in a way that fits on the status line
+pub fn read_error(path: &str, e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => format!("{}: no such file", path),
+        io::ErrorKind::IsADirectory => format!("{} is a directory", path),
+        io::ErrorKind::InvalidData => format!("{} is not valid UTF-8", path),
+        _ => format!("Failed to open {}: {}", path, e),
+    }
+}
+
+/// Replaces a leading `~` with the home directory
+pub fn expand_home(path: &str) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => return String::from(path),
+    };
+    match std::env::var("HOME") {
+        Ok(home) => format!("{}{}", home.trim_end_matches('/'), rest),
+        Err(_) => String::from(path),
+    }
+}
+
+/// Every path that `input` can be completed to, sorted and written the way it was typed so far.
+/// Directories end in a slash, hidden files only show up once their `.` is typed.
+pub fn complete_path(input: &str) -> Vec<String> {
+    if input == "~" {
+        return vec![String::from("~/")];
+    }
+
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { String::from(".") } else { expand_home(dir) }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut completions: Vec<String> = entries.filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Follows symlinks, so links to directories complete like directories
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    completions.sort();
+    completions
+}
+
+/// The longest start that all `paths` share
+pub fn common_prefix(paths: &[String]) -> &str {
+    let first = match paths.first() {
+        Some(first) => first.as_str(),
+        None => return "",
+    };
+    let len = paths[1..].iter().fold(first.len(), |len, path| {
+        first.char_indices()
+            .zip(path.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+    });
+    &first[..len]
+}
+
+/// Writes to a temporary file next to `path` first and renames it over the original, so a failed
+/// write never leaves a half written file behind
+pub fn write_file(path: &str, content: &str) -> Result<(), io::Error> {
+    // Write through symlinks instead of replacing them
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(_) => Path::new(path).to_path_buf(),
+    };
+
+    let file_name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let tmp_path = path.with_file_name(format!(".{}.awildtxt-tmp", file_name.to_string_lossy()));
+
+    let permissions = fs::metadata(&path).ok().map(|m| m.permissions());
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+
+        if let Some(permissions) = permissions {
+            fs::set_permissions(&tmp_path, permissions)?;
+        }
+
+        fs::rename(&tmp_path, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::file::{common_prefix, complete_path, read_error, read_file, write_file};
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn write_new_file() {
+        let dir = TempDir::new("write-new");
+        let path = dir.join("new.txt");
+
+        write_file(path.to_str().unwrap(), "Hello\nWorld").unwrap();
+
+        assert_eq!(read_file(path.to_str().unwrap()).unwrap(), "Hello\nWorld");
+        assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn overwrite_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("overwrite");
+        let path = dir.join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write_file(path.to_str().unwrap(), "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+    }
+
+    #[test]
+    fn write_into_missing_directory_fails() {
+        let dir = TempDir::new("missing");
+        let path = dir.join("missing").join("file.txt");
+
+        assert!(write_file(path.to_str().unwrap(), "content").is_err());
+    }
+
+    #[test]
+    fn complete_paths_in_directory() {
+        let dir = TempDir::new("complete");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("notes.md"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let input = format!("{}/", dir.to_str().unwrap());
+
+        let completions = complete_path(&format!("{}no", input));
+        assert_eq!(completions, vec![format!("{}notes.md", input), format!("{}notes.txt", input)]);
+        assert_eq!(common_prefix(&completions), format!("{}notes.", input));
+
+        assert_eq!(complete_path(&format!("{}s", input)), vec![format!("{}src/", input)]);
+        assert_eq!(complete_path(&input).len(), 3);
+        assert_eq!(complete_path(&format!("{}.", input)), vec![format!("{}.hidden", input)]);
+        assert!(complete_path(&format!("{}missing/", input)).is_empty());
+    }
+
+    #[test]
+    fn read_errors_name_the_problem() {
+        let dir = TempDir::new("read-errors");
+        fs::write(dir.join("binary"), [0xff, 0xfe]).unwrap();
+        fs::create_dir(dir.join("sub")).unwrap();
+        let read = |name: &str| {
+            let path = dir.join(name);
+            read_error(name, &read_file(path.to_str().unwrap()).unwrap_err())
+        };
+
+        assert_eq!(read("missing"), "missing: no such file");
+        assert_eq!(read("sub"), "sub is a directory");
+        assert_eq!(read("binary"), "binary is not valid UTF-8");
+    }
+}
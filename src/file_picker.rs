@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+// Listing stops here so huge directories don't hang the editor
+const MAX_ENTRIES: usize = 20_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    // Relative to the directory of the picker
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+impl Entry {
+    /// Directories end in a slash to tell them apart
+    pub fn display(&self) -> String {
+        let path = self.path.to_string_lossy();
+        if self.is_dir { format!("{}/", path) } else { path.into_owned() }
+    }
+}
+
+/// Score of `candidate` when it contains the characters of `query` in order, ignoring case.
+/// Consecutive characters and characters at the start of a path segment or word score higher,
+/// shorter candidates win ties.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let mut score = 0;
+    let mut query_chars = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous: Option<char> = None;
+    let mut previous_matched = false;
+
+    for c in candidate.chars() {
+        let wanted = match query_chars.peek() {
+            Some(wanted) => *wanted,
+            None => break,
+        };
+
+        let matched = c.to_lowercase().eq(std::iter::once(wanted));
+        if matched {
+            query_chars.next();
+            score += 1;
+            if previous_matched {
+                score += 5;
+            }
+            if previous.is_none_or(|p| matches!(p, '/' | '_' | '-' | '.' | ' ')) {
+                score += 8;
+            }
+        }
+
+        previous_matched = matched;
+        previous = Some(c);
+    }
+
+    if query_chars.peek().is_some() {
+        return None;
+    }
+    Some(score * 100 - candidate.chars().count() as i32)
+}
+
+/// Lists everything below a directory that is not ignored by `.gitignore` and filters it as a query
+/// is typed
+pub struct FilePicker {
+    pub dir: PathBuf,
+    entries: Vec<Entry>,
+    pub query: String,
+    // Indices into `entries` of the matches, best first
+    matches: Vec<usize>,
+    pub selected: usize,
+    // Why the chosen file could not be opened
+    pub error: Option<String>,
+}
+
+impl FilePicker {
+    pub fn new(dir: PathBuf) -> Self {
+        let mut picker = FilePicker {
+            dir: PathBuf::new(),
+            entries: Vec::new(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            error: None,
+        };
+        picker.change_dir(dir);
+        picker
+    }
+
+    /// Lists `dir` and starts over with an empty query
+    pub fn change_dir(&mut self, dir: PathBuf) {
+        self.entries = Self::list(&dir);
+        self.dir = dir;
+        self.query.clear();
+        self.error = None;
+        self.filter();
+    }
+
+    /// Goes to the parent directory, returns false at the root
+    pub fn parent_dir(&mut self) -> bool {
+        match self.dir.parent() {
+            Some(parent) => {
+                self.change_dir(parent.to_path_buf());
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn list(dir: &Path) -> Vec<Entry> {
+        let walker = WalkBuilder::new(dir)
+            // `.gitignore` applies outside of git repositories too
+            .require_git(false)
+            .sort_by_file_path(|a, b| a.cmp(b))
+            .build();
+
+        walker.filter_map(Result::ok)
+            .filter(|entry| entry.depth() > 0)
+            .filter_map(|entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                let path = entry.path().strip_prefix(dir).ok()?.to_path_buf();
+                Some(Entry { path, is_dir })
+            })
+            .take(MAX_ENTRIES)
+            .collect()
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.query.push_str(text);
+        self.filter();
+    }
+
+    /// Returns false when the query was already empty
+    pub fn pop(&mut self) -> bool {
+        if self.query.pop().is_none() {
+            return false;
+        }
+        self.filter();
+        true
+    }
+
+    fn filter(&mut self) {
+        self.selected = 0;
+        if self.query.is_empty() {
+            self.matches = (0..self.entries.len()).collect();
+            return;
+        }
+
+        let mut scored: Vec<(i32, usize)> = self.entries.iter().enumerate()
+            .filter_map(|(i, entry)| fuzzy_score(&self.query, &entry.display()).map(|score| (score, i)))
+            .collect();
+        // Stable, so equal scores keep the listing order
+        scored.sort_by_key(|(score, _)| -score);
+
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+    }
+
+    pub fn matches(&self) -> impl Iterator<Item = &Entry> {
+        self.matches.iter().map(|i| &self.entries[*i])
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.matches.get(self.selected).map(|i| &self.entries[*i])
+    }
+
+    pub fn move_selection(&mut self, delta: i32) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let last = self.matches.len() as i64 - 1;
+        self.selected = (self.selected as i64 + delta as i64).clamp(0, last) as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::file_picker::{fuzzy_score, FilePicker};
+    use crate::test_util::TempDir;
+
+    fn displayed(picker: &FilePicker) -> Vec<String> {
+        picker.matches().map(|entry| entry.display()).collect()
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        assert_eq!(fuzzy_score("xyz", "src/main.rs"), None);
+        assert!(fuzzy_score("", "anything").is_some());
+        // Segment starts and runs of characters beat scattered matches
+        assert!(fuzzy_score("main", "src/main.rs") > fuzzy_score("main", "src/my_animation.rs"));
+        assert!(fuzzy_score("MR", "src/main.rs").is_some());
+        assert!(fuzzy_score("ed", "editor.rs") > fuzzy_score("ed", "src/editor.rs"));
+    }
+
+    #[test]
+    fn lists_files_respecting_gitignore() {
+        let dir = TempDir::new("picker-gitignore");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join("src").join("main.rs"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("debug.log"), "").unwrap();
+        fs::write(dir.join("target").join("out"), "").unwrap();
+
+        let mut picker = FilePicker::new(dir.to_path_buf());
+        assert_eq!(displayed(&picker), vec!["notes.txt", "src/", "src/main.rs"]);
+
+        picker.push_str("mn");
+        assert_eq!(displayed(&picker), vec!["src/main.rs"]);
+        picker.push_str("q");
+        assert_eq!(picker.selected(), None);
+        assert!(picker.pop());
+
+        picker.change_dir(dir.join("src"));
+        assert_eq!(displayed(&picker), vec!["main.rs"]);
+        assert!(!picker.pop());
+        assert!(picker.parent_dir());
+        assert_eq!(picker.dir, *dir);
+    }
+
+    #[test]
+    fn selection_stays_in_matches() {
+        let dir = TempDir::new("picker-selection");
+        fs::write(dir.join("a"), "").unwrap();
+        fs::write(dir.join("b"), "").unwrap();
+
+        let mut picker = FilePicker::new(dir.to_path_buf());
+        picker.move_selection(5);
+        assert_eq!(picker.selected().unwrap().display(), "b");
+        picker.move_selection(-1);
+        assert_eq!(picker.selected().unwrap().display(), "a");
+        picker.move_selection(-1);
+        assert_eq!(picker.selected().unwrap().display(), "a");
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use sdl2::{pixels::{Color, PixelFormatEnum}, render::{BlendMode, Canvas, Texture, TextureAccess, TextureCreator}, video::{Window, WindowContext}, rect::Rect, ttf::Font};
+
+const ATLAS_SIZE: u32 = 2048;
+// When every atlas is full the least recently used glyphs get replaced
+const MAX_ATLASES: usize = 4;
+const REPLACEMENT_CHARS: [char; 2] = ['\u{fffd}', '?'];
+
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    atlas: usize,
+    cell: u32,
+    last_used: u64,
+}
+
+/// Rasterizes glyphs the first time they are drawn and keeps them around in atlas textures
+pub struct GlyphCache<'a> {
+    creator: &'a TextureCreator<WindowContext>,
+    font: &'a Font<'a, 'a>,
+    font_size: (u32, u32),
+    atlases: Vec<Texture<'a>>,
+    glyphs: HashMap<char, Glyph>,
+    // Next unused cell in the last atlas
+    next_cell: u32,
+    color: Color,
+    uses: u64,
+}
+
+impl<'a> GlyphCache<'a> {
+    pub fn new(creator: &'a TextureCreator<WindowContext>, font: &'a Font<'a, 'a>, font_size: (u32, u32)) -> Self {
+        GlyphCache {
+            creator,
+            font,
+            font_size,
+            atlases: Vec::new(),
+            glyphs: HashMap::new(),
+            next_cell: 0,
+            color: Color::RGB(255, 255, 255),
+            uses: 0,
+        }
+    }
+
+    pub fn set_color(&mut self, color: Color) {
+        self.color = color;
+    }
+
+    fn cells_per_row(&self) -> u32 {
+        ATLAS_SIZE / self.font_size.0
+    }
+
+    fn cells_per_atlas(&self) -> u32 {
+        self.cells_per_row() * (ATLAS_SIZE / self.font_size.1)
+    }
+
+    fn cell_rect(&self, cell: u32) -> Rect {
+        let x = (cell % self.cells_per_row()) * self.font_size.0;
+        let y = (cell / self.cells_per_row()) * self.font_size.1;
+        Rect::new(x as i32, y as i32, self.font_size.0, self.font_size.1)
+    }
+
+    /// Characters the font has no glyph for are drawn as a replacement character
+    fn resolve(&self, c: char) -> char {
+        if self.font.find_glyph(c).is_some() {
+            return c;
+        }
+
+        REPLACEMENT_CHARS.into_iter()
+            .find(|r| self.font.find_glyph(*r).is_some())
+            .unwrap_or(' ')
+    }
+
+    fn create_atlas(&self, canvas: &mut Canvas<Window>) -> Texture<'a> {
+        let mut texture = self.creator.create_texture(
+            PixelFormatEnum::RGBA32,
+            TextureAccess::Target,
+            ATLAS_SIZE,
+            ATLAS_SIZE
+        ).unwrap();
+        texture.set_blend_mode(BlendMode::Blend);
+
+        canvas.with_texture_canvas(&mut texture, |canv| {
+            canv.set_draw_color(Color::RGBA(0, 0, 0, 0));
+            canv.clear();
+        }).expect("Failed to create glyph atlas");
+
+        texture
+    }
+
+    /// Finds room for a new glyph, creating an atlas or evicting the least recently used glyph
+    fn allocate(&mut self, canvas: &mut Canvas<Window>) -> (usize, u32) {
+        if self.atlases.is_empty() || self.next_cell >= self.cells_per_atlas() {
+            if self.atlases.len() < MAX_ATLASES {
+                let atlas = self.create_atlas(canvas);
+                self.atlases.push(atlas);
+                self.next_cell = 0;
+            } else {
+                let (&evicted, &glyph) = self.glyphs.iter()
+                    .min_by_key(|(_, g)| g.last_used)
+                    .expect("Full glyph atlases without glyphs");
+                self.glyphs.remove(&evicted);
+                return (glyph.atlas, glyph.cell);
+            }
+        }
+
+        let cell = self.next_cell;
+        self.next_cell += 1;
+        (self.atlases.len() - 1, cell)
+    }
+
+    fn rasterize(&mut self, canvas: &mut Canvas<Window>, c: char) -> Glyph {
+        let (atlas, cell) = self.allocate(canvas);
+        let r = self.cell_rect(cell);
+
+        let resolved = self.resolve(c);
+        let char_texture = if resolved.is_whitespace() {
+            None
+        } else {
+            self.font.render_char(resolved)
+                .blended(Color::RGBA(255, 255, 255, 255))
+                .ok()
+                .and_then(|surface| self.creator.create_texture_from_surface(&surface).ok())
+        };
+
+        canvas.with_texture_canvas(&mut self.atlases[atlas], |canv| {
+            // The cell might still hold an evicted glyph
+            canv.set_blend_mode(BlendMode::None);
+            canv.set_draw_color(Color::RGBA(0, 0, 0, 0));
+            canv.fill_rect(r).unwrap();
+
+            if let Some(char_texture) = &char_texture {
+                canv.copy(char_texture, None, Some(r)).unwrap();
+            }
+        }).expect("Failed to rasterize glyph");
+
+        Glyph {
+            atlas,
+            cell,
+            last_used: self.uses,
+        }
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, c: char, x: i32, y: i32) {
+        self.uses += 1;
+
+        let glyph = match self.glyphs.get_mut(&c) {
+            Some(glyph) => {
+                glyph.last_used = self.uses;
+                *glyph
+            },
+            None => {
+                let glyph = self.rasterize(canvas, c);
+                self.glyphs.insert(c, glyph);
+                glyph
+            },
+        };
+
+        let src = self.cell_rect(glyph.cell);
+        let dst = Rect::new(x, y, self.font_size.0, self.font_size.1);
+
+        let texture = &mut self.atlases[glyph.atlas];
+        texture.set_color_mod(self.color.r, self.color.g, self.color.b);
+        canvas.copy(texture, Some(src), Some(dst)).unwrap();
+    }
+}
@@ -0,0 +1,596 @@
+use std::{fmt, path::PathBuf};
+
+use sdl2::keyboard::{Keycode, Mod};
+
+use crate::editor::Mode;
+
+/// Everything a key can be bound to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    PageUp,
+    PageDown,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    FindChar,
+    TillChar,
+    SearchForward,
+    SearchBackward,
+    NextMatch,
+    PreviousMatch,
+    Delete,
+    Change,
+    Yank,
+    SelectRegister,
+    PasteAfter,
+    PasteBefore,
+    CopyToClipboard,
+    PasteFromClipboard,
+    Indent,
+    Outdent,
+    ToggleCase,
+    Lowercase,
+    Uppercase,
+    InnerObject,
+    AroundObject,
+    InsertMode,
+    VisualMode,
+    VisualLineMode,
+    OpenCommandLine,
+    NormalMode,
+    DeleteChar,
+    DeleteBackward,
+    DeleteForward,
+    Newline,
+    Undo,
+    Redo,
+    OpenFile,
+    FilePicker,
+    Save,
+    CloseBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    BufferList,
+    ToggleWrap,
+    AddCursorAtNextMatch,
+    AddCursorsToLines,
+}
+
+const COMMAND_NAMES: [(&str, Command); 55] = [
+    ("move_left", Command::MoveLeft),
+    ("move_right", Command::MoveRight),
+    ("move_up", Command::MoveUp),
+    ("move_down", Command::MoveDown),
+    ("page_up", Command::PageUp),
+    ("page_down", Command::PageDown),
+    ("word_forward", Command::WordForward),
+    ("word_backward", Command::WordBackward),
+    ("word_end", Command::WordEnd),
+    ("line_start", Command::LineStart),
+    ("line_end", Command::LineEnd),
+    ("first_line", Command::FirstLine),
+    ("last_line", Command::LastLine),
+    ("find_char", Command::FindChar),
+    ("till_char", Command::TillChar),
+    ("search_forward", Command::SearchForward),
+    ("search_backward", Command::SearchBackward),
+    ("next_match", Command::NextMatch),
+    ("previous_match", Command::PreviousMatch),
+    ("delete", Command::Delete),
+    ("change", Command::Change),
+    ("yank", Command::Yank),
+    ("select_register", Command::SelectRegister),
+    ("paste_after", Command::PasteAfter),
+    ("paste_before", Command::PasteBefore),
+    ("copy_to_clipboard", Command::CopyToClipboard),
+    ("paste_from_clipboard", Command::PasteFromClipboard),
+    ("indent", Command::Indent),
+    ("outdent", Command::Outdent),
+    ("toggle_case", Command::ToggleCase),
+    ("lowercase", Command::Lowercase),
+    ("uppercase", Command::Uppercase),
+    ("inner_object", Command::InnerObject),
+    ("around_object", Command::AroundObject),
+    ("insert_mode", Command::InsertMode),
+    ("visual_mode", Command::VisualMode),
+    ("visual_line_mode", Command::VisualLineMode),
+    ("command_line", Command::OpenCommandLine),
+    ("normal_mode", Command::NormalMode),
+    ("delete_char", Command::DeleteChar),
+    ("delete_backward", Command::DeleteBackward),
+    ("delete_forward", Command::DeleteForward),
+    ("newline", Command::Newline),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+    ("open_file", Command::OpenFile),
+    ("file_picker", Command::FilePicker),
+    ("save", Command::Save),
+    ("close_buffer", Command::CloseBuffer),
+    ("next_buffer", Command::NextBuffer),
+    ("previous_buffer", Command::PreviousBuffer),
+    ("buffer_list", Command::BufferList),
+    ("toggle_wrap", Command::ToggleWrap),
+    ("add_cursor_at_next_match", Command::AddCursorAtNextMatch),
+    ("add_cursors_to_lines", Command::AddCursorsToLines),
+];
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Command> {
+        COMMAND_NAMES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+    }
+}
+
+const NAMED_KEYS: [(&str, Keycode); 15] = [
+    ("escape", Keycode::Escape),
+    ("esc", Keycode::Escape),
+    ("return", Keycode::Return),
+    ("enter", Keycode::Return),
+    ("backspace", Keycode::Backspace),
+    ("delete", Keycode::Delete),
+    ("tab", Keycode::Tab),
+    ("left", Keycode::Left),
+    ("right", Keycode::Right),
+    ("up", Keycode::Up),
+    ("down", Keycode::Down),
+    ("pageup", Keycode::PageUp),
+    ("pagedown", Keycode::PageDown),
+    ("home", Keycode::Home),
+    ("end", Keycode::End),
+];
+
+/// A key of a chord. Printable keys are told apart by the character they type, which follows the
+/// keyboard layout, only keys that type nothing use their keycode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Code(Keycode),
+    Char(char),
+}
+
+/// A key together with the modifiers held down, left and right modifiers are not told apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyChord {
+    pub key: Key,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl KeyChord {
+    pub fn new(key: Key) -> Self {
+        KeyChord { key, ctrl: false, alt: false, shift: false }
+    }
+
+    /// A character typed without ctrl or alt, as it arrives in a text input event
+    pub fn from_text(c: char) -> Self {
+        KeyChord::new(Key::Char(c))
+    }
+
+    /// The chord of a key down event, printable keys without ctrl or alt are left to the text input
+    /// event that follows them
+    pub fn from_event(keycode: Keycode, keymod: Mod) -> Option<Self> {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let alt = keymod.intersects(Mod::LALTMOD | Mod::RALTMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+
+        // Printable keys have their unshifted ASCII value as keycode
+        let code = keycode as i32;
+        let key = if (0x20..0x7f).contains(&code) {
+            if !ctrl && !alt {
+                return None;
+            }
+            Key::Char(char::from(code as u8))
+        } else {
+            Key::Code(keycode)
+        };
+
+        Some(KeyChord { key, ctrl, alt, shift })
+    }
+
+    /// Parses chords like `x`, `X`, `:`, `ctrl+o`, `alt+z` or `ctrl+pagedown`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts: Vec<&str> = text.split('+').collect();
+        // `+` itself and chords ending in it, like `ctrl++`
+        if text.ends_with("++") || text == "+" {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+
+        let key = parts.pop()?;
+        let mut chord = Self::parse_key(key)?;
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" => chord.ctrl = true,
+                "alt" => chord.alt = true,
+                "shift" => chord.shift = true,
+                _ => return None,
+            }
+        }
+
+        // With ctrl or alt held no text is typed, so the key is known by its unshifted ASCII value
+        if let Key::Char(c) = chord.key {
+            if chord.ctrl || chord.alt {
+                if !c.is_ascii() {
+                    return None;
+                }
+                chord.shift |= c.is_ascii_uppercase();
+                chord.key = Key::Char(c.to_ascii_lowercase());
+            } else if chord.shift {
+                return None;
+            }
+        }
+
+        Some(chord)
+    }
+
+    fn parse_key(key: &str) -> Option<Self> {
+        let lower = key.to_lowercase();
+        if lower == "space" {
+            return Some(KeyChord::from_text(' '));
+        }
+        if let Some((_, keycode)) = NAMED_KEYS.iter().find(|(name, _)| *name == lower) {
+            return Some(KeyChord::new(Key::Code(*keycode)));
+        }
+
+        let mut chars = key.chars();
+        let c = chars.next()?;
+        if chars.next().is_some() || c.is_control() || c.is_whitespace() {
+            return None;
+        }
+
+        Some(KeyChord::from_text(c))
+    }
+}
+
+/// The modes a binding can be made for, global bindings apply in normal, insert and visual mode.
+/// Operator bindings take precedence while an operator waits for its motion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeymapMode {
+    Global,
+    Normal,
+    Insert,
+    Visual,
+    Operator,
+}
+
+impl KeymapMode {
+    fn from_name(name: &str) -> Option<KeymapMode> {
+        match name {
+            "global" => Some(KeymapMode::Global),
+            "normal" => Some(KeymapMode::Normal),
+            "insert" => Some(KeymapMode::Insert),
+            "visual" => Some(KeymapMode::Visual),
+            "operator" => Some(KeymapMode::Operator),
+            _ => None,
+        }
+    }
+
+    fn from_mode(mode: Mode) -> Option<KeymapMode> {
+        match mode {
+            Mode::Normal => Some(KeymapMode::Normal),
+            Mode::Insert => Some(KeymapMode::Insert),
+            Mode::Visual | Mode::VisualLine => Some(KeymapMode::Visual),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyMatch {
+    Command(Command),
+    // The keys are the start of a longer sequence
+    Pending,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+struct Binding {
+    mode: KeymapMode,
+    keys: Vec<KeyChord>,
+    command: Command,
+}
+
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+const DEFAULT_KEYMAP: &str = "
+global ctrl+o = open_file
+global ctrl+p = file_picker
+global ctrl+s = save
+global ctrl+w = close_buffer
+global ctrl+pagedown = next_buffer
+global ctrl+pageup = previous_buffer
+global ctrl+b = buffer_list
+global alt+z = toggle_wrap
+global left = move_left
+global right = move_right
+global up = move_up
+global down = move_down
+global pageup = page_up
+global pagedown = page_down
+global home = line_start
+global end = line_end
+
+normal h = move_left
+normal backspace = move_left
+normal l = move_right
+normal j = move_down
+normal k = move_up
+normal i = insert_mode
+normal v = visual_mode
+normal V = visual_line_mode
+normal : = command_line
+normal x = delete_char
+normal u = undo
+normal ctrl+r = redo
+normal w = word_forward
+normal b = word_backward
+normal e = word_end
+normal 0 = line_start
+normal $ = line_end
+normal g g = first_line
+normal G = last_line
+normal f = find_char
+normal t = till_char
+normal / = search_forward
+normal ? = search_backward
+normal n = next_match
+normal N = previous_match
+normal d = delete
+normal c = change
+normal y = yank
+normal \" = select_register
+normal p = paste_after
+normal P = paste_before
+normal > > = indent
+normal < < = outdent
+normal ~ = toggle_case
+normal ctrl+n = add_cursor_at_next_match
+normal escape = normal_mode
+
+operator i = inner_object
+operator a = around_object
+
+insert escape = normal_mode
+insert backspace = delete_backward
+insert delete = delete_forward
+insert return = newline
+insert ctrl+c = copy_to_clipboard
+insert ctrl+v = paste_from_clipboard
+
+visual h = move_left
+visual backspace = move_left
+visual l = move_right
+visual j = move_down
+visual k = move_up
+visual escape = normal_mode
+visual v = visual_mode
+visual V = visual_line_mode
+visual w = word_forward
+visual b = word_backward
+visual e = word_end
+visual 0 = line_start
+visual $ = line_end
+visual g g = first_line
+visual G = last_line
+visual f = find_char
+visual t = till_char
+visual n = next_match
+visual N = previous_match
+visual i = inner_object
+visual a = around_object
+visual d = delete
+visual x = delete
+visual c = change
+visual y = yank
+visual \" = select_register
+visual ctrl+c = copy_to_clipboard
+visual > = indent
+visual < = outdent
+visual ~ = toggle_case
+visual u = lowercase
+visual U = uppercase
+visual ctrl+n = add_cursors_to_lines
+";
+
+/// `$XDG_CONFIG_HOME/awildtxt/keymap`, falling back to `~/.config` when it is not set
+pub fn config_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if PathBuf::from(&dir).is_absolute() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join("awildtxt").join("keymap"))
+}
+
+impl Keymap {
+    /// The default bindings
+    pub fn new() -> Self {
+        let mut keymap = Keymap { bindings: Vec::new() };
+        let errors = keymap.apply_config(DEFAULT_KEYMAP);
+        debug_assert!(errors.is_empty(), "Invalid default keymap: {:?}", errors);
+        keymap
+    }
+
+    /// Adds the bindings of a config, replacing bindings for the same keys. Every line is
+    /// `<mode> <keys> = <command>` with the keys of a sequence separated by spaces. Invalid lines
+    /// are skipped and reported.
+    pub fn apply_config(&mut self, config: &str) -> Vec<KeymapError> {
+        let mut errors = Vec::new();
+        for (i, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Self::parse_binding(line) {
+                Ok(binding) => self.bind(binding),
+                Err(message) => errors.push(KeymapError { line: i + 1, message }),
+            }
+        }
+
+        errors
+    }
+
+    fn parse_binding(line: &str) -> Result<Binding, String> {
+        let (keys, command) = line.rsplit_once('=')
+            .ok_or_else(|| String::from("expected `<mode> <keys> = <command>`"))?;
+
+        let command_name = command.trim();
+        let command = Command::from_name(command_name)
+            .ok_or_else(|| format!("unknown command `{}`", command_name))?;
+
+        let mut words = keys.split_whitespace();
+        let mode_name = words.next().unwrap_or("");
+        let mode = KeymapMode::from_name(mode_name)
+            .ok_or_else(|| format!("unknown mode `{}`, expected global, normal, insert, visual or operator", mode_name))?;
+
+        let keys = words
+            .map(|word| KeyChord::parse(word).ok_or_else(|| format!("unknown key `{}`", word)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(String::from("no keys to bind"));
+        }
+
+        Ok(Binding { mode, keys, command })
+    }
+
+    fn bind(&mut self, binding: Binding) {
+        self.bindings.retain(|b| b.mode != binding.mode || b.keys != binding.keys);
+        self.bindings.push(binding);
+    }
+
+    fn lookup_mode(&self, mode: KeymapMode, keys: &[KeyChord]) -> KeyMatch {
+        let mut pending = false;
+        for binding in self.bindings.iter().filter(|b| b.mode == mode) {
+            if binding.keys == keys {
+                return KeyMatch::Command(binding.command);
+            }
+            pending |= binding.keys.starts_with(keys);
+        }
+
+        if pending { KeyMatch::Pending } else { KeyMatch::None }
+    }
+
+    /// Bindings for the mode itself take precedence over global ones
+    pub fn lookup(&self, mode: Mode, operator_pending: bool, keys: &[KeyChord]) -> KeyMatch {
+        let mode = match KeymapMode::from_mode(mode) {
+            Some(mode) => mode,
+            None => return KeyMatch::None,
+        };
+
+        if operator_pending {
+            let result = self.lookup_mode(KeymapMode::Operator, keys);
+            if result != KeyMatch::None {
+                return result;
+            }
+        }
+
+        match self.lookup_mode(mode, keys) {
+            KeyMatch::None => self.lookup_mode(KeymapMode::Global, keys),
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::{Keycode, Mod};
+
+    use crate::editor::Mode;
+    use crate::keymap::{Command, Key, KeyChord, KeyMatch, Keymap, COMMAND_NAMES};
+
+    fn chord(text: &str) -> KeyChord {
+        KeyChord::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parse_chords() {
+        assert_eq!(chord("x"), KeyChord::from_text('x'));
+        assert_eq!(chord("X"), KeyChord::from_text('X'));
+        assert_eq!(chord("ctrl+PageDown"), KeyChord { ctrl: true, ..KeyChord::new(Key::Code(Keycode::PageDown)) });
+        assert_eq!(chord(":"), KeyChord::from_text(':'));
+        assert_eq!(chord("\u{e9}"), KeyChord::from_text('\u{e9}'));
+        assert_eq!(chord("space"), KeyChord::from_text(' '));
+        assert_eq!(chord("ctrl++"), KeyChord { ctrl: true, ..KeyChord::from_text('+') });
+        assert_eq!(chord("ctrl+X"), KeyChord { ctrl: true, shift: true, ..KeyChord::from_text('x') });
+        assert_eq!(KeyChord::parse("shift+x"), None);
+        assert_eq!(KeyChord::parse("hyper+x"), None);
+        assert_eq!(KeyChord::parse("xy"), None);
+    }
+
+    #[test]
+    fn every_command_has_a_name() {
+        // Names are listed in declaration order, so a variant without one shifts everything after it
+        for (i, (name, command)) in COMMAND_NAMES.iter().enumerate() {
+            assert_eq!(*command as usize, i, "{} is out of order", name);
+            assert_eq!(Command::from_name(name), Some(*command));
+        }
+        assert_eq!(COMMAND_NAMES.len(), Command::AddCursorsToLines as usize + 1);
+    }
+
+    #[test]
+    fn right_ctrl_matches_ctrl_bindings() {
+        let keymap = Keymap::new();
+        let keys = [KeyChord::from_event(Keycode::O, Mod::RCTRLMOD).unwrap()];
+        assert_eq!(keymap.lookup(Mode::Insert, false, &keys), KeyMatch::Command(Command::OpenFile));
+    }
+
+    #[test]
+    fn printable_keys_are_left_to_text_input() {
+        // Whatever shift turns the key into depends on the layout, only the text input knows
+        assert_eq!(KeyChord::from_event(Keycode::Semicolon, Mod::LSHIFTMOD), None);
+        assert_eq!(KeyChord::from_event(Keycode::X, Mod::NUMMOD), None);
+        assert_eq!(KeyChord::from_event(Keycode::Escape, Mod::LSHIFTMOD), Some(KeyChord { shift: true, ..chord("escape") }));
+        assert_eq!(KeyChord::from_event(Keycode::X, Mod::LCTRLMOD | Mod::LSHIFTMOD), Some(chord("ctrl+X")));
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let mut keymap = Keymap::new();
+        let errors = keymap.apply_config("# comment\nnormal x = undo\nnormal g g = move_up\n");
+        assert!(errors.is_empty());
+
+        assert_eq!(keymap.lookup(Mode::Normal, false, &[chord("x")]), KeyMatch::Command(Command::Undo));
+        assert_eq!(keymap.lookup(Mode::Normal, false, &[chord("g")]), KeyMatch::Pending);
+        assert_eq!(keymap.lookup(Mode::Normal, false, &[chord("g"), chord("g")]), KeyMatch::Command(Command::MoveUp));
+        assert_eq!(keymap.lookup(Mode::Normal, true, &[chord("i")]), KeyMatch::Command(Command::InnerObject));
+        // Other modes keep their own bindings
+        assert_eq!(keymap.lookup(Mode::Visual, false, &[chord("x")]), KeyMatch::Command(Command::Delete));
+    }
+
+    #[test]
+    fn config_errors_name_the_line() {
+        let mut keymap = Keymap::new();
+        let errors = keymap.apply_config("normal x = undo\nnormal y = yank_everything\nsideways z = undo\nnormal = undo\nnormal ctrl+\u{e9} = undo");
+
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec![
+            "line 2: unknown command `yank_everything`",
+            "line 3: unknown mode `sideways`, expected global, normal, insert, visual or operator",
+            "line 4: no keys to bind",
+            "line 5: unknown key `ctrl+\u{e9}`",
+        ]);
+        // Valid lines are still applied
+        assert_eq!(keymap.lookup(Mode::Normal, false, &[chord("x")]), KeyMatch::Command(Command::Undo));
+    }
+}
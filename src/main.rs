@@ -1,50 +1,32 @@
 mod piece_table;
+mod piece_tree;
 mod cursor;
 mod buffer;
+mod buffer_manager;
 mod file;
-
-use piece_table::PieceTable;
-use cursor::Cursor;
-use file::read_file;
-use sdl2::{pixels::{Color, PixelFormatEnum}, event::Event, keyboard::Keycode, render::{Canvas, Texture, TextureCreator, TextureAccess}, video::{Window, WindowContext}, rect::Rect, ttf::Font};
-use buffer::Buffer;
-
-type GlyphPosition = (i32, i32);
-
-fn create_glyph_atlas<'canvas>(canvas: &mut Canvas<Window>, creator: &'canvas TextureCreator<WindowContext>, font: &Font, font_size: (u32, u32)) -> (Texture<'canvas>, [GlyphPosition; 128]) {
-    let mut texture = creator.create_texture(
-        PixelFormatEnum::RGBA32,
-        TextureAccess::Target,
-        2048,
-        2048
-    ).unwrap();
-
-    let mut mapping: [GlyphPosition; 128] = [(0,0);128];
-
-    canvas.with_texture_canvas(&mut texture, |canv| {
-        for i in 0..128 {
-            let c_opt = char::from_u32(i);
-            if let Some(c) = c_opt {
-                let r = Rect::new((i * font_size.0) as i32, 0, font_size.0, font_size.1);
-                if c == '\0' {
-                    continue;
-                }
-
-                let surface = font.render_char(c)
-                    .blended(Color::RGBA(255, 255, 255, 255))
-                    .unwrap();
-                let char_texture = creator.create_texture_from_surface(&surface).unwrap();
-
-                canv.copy(&char_texture, None, Some(r)).unwrap();
-                mapping[i as usize] = (r.x, r.y);
-            }
-        }
-    }).expect("Failed to create glyph atlas");
-
-    (texture, mapping)
-}
-
-fn render_text(canvas: &mut Canvas<Window>, glyph_atlas: &mut Texture, mapping: [GlyphPosition; 128], font_size: (u32, u32), text: &String, x: i32, y: i32) {
+mod glyph_cache;
+mod viewport;
+mod editor;
+mod keymap;
+mod motion;
+mod text_object;
+mod register;
+mod search;
+mod substitute;
+mod ex;
+mod file_picker;
+mod args;
+mod status_line;
+#[cfg(test)]
+mod test_util;
+
+use sdl2::{pixels::Color, event::Event, render::Canvas, video::Window, rect::Rect};
+use args::Args;
+use editor::{Editor, Mode, Prompt};
+use glyph_cache::GlyphCache;
+use status_line::StatusLine;
+
+fn render_text(canvas: &mut Canvas<Window>, glyph_cache: &mut GlyphCache, font_size: (u32, u32), text: &str, x: i32, y: i32) {
     let mut line = 0;
     let mut carriage = 0;
     for c in text.chars() {
@@ -54,18 +36,49 @@ fn render_text(canvas: &mut Canvas<Window>, glyph_atlas: &mut Texture, mapping:
             continue;
         }
 
-        let pos = mapping[c as usize];
-        let src = Rect::new(pos.0, pos.1, font_size.0, font_size.1);
-        let dst = Rect::new(x + (carriage * font_size.0) as i32, y + (font_size.1 * line) as i32, font_size.0, font_size.1);
+        glyph_cache.draw(canvas, c, x + (carriage * font_size.0) as i32, y + (font_size.1 * line) as i32);
         carriage+=1;
-        canvas.copy(&glyph_atlas, Some(src), Some(dst)).unwrap();
     };
 }
 
+/// A full width bar with black text, used for prompts
+fn render_bar(canvas: &mut Canvas<Window>, glyph_cache: &mut GlyphCache, font_size: (u32, u32), text: &str, y: i32) {
+    let width = canvas.viewport().width();
+    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    canvas.fill_rect(Rect::new(0, y, width, font_size.1)).unwrap();
+    glyph_cache.set_color(Color::RGB(0, 0, 0));
+    render_text(canvas, glyph_cache, font_size, text, 3, y);
+}
+
+/// Entries of a picker stacked upwards from `bottom`, the selected one inverted
+fn render_list(canvas: &mut Canvas<Window>, glyph_cache: &mut GlyphCache, font_size: (u32, u32), entries: &[String], selected: Option<usize>, bottom: i32) {
+    let width = canvas.viewport().width();
+    let top = bottom - entries.len() as i32 * font_size.1 as i32;
+    for (i, entry) in entries.iter().enumerate() {
+        let y = top + i as i32 * font_size.1 as i32;
+        if selected == Some(i) {
+            canvas.set_draw_color(Color::RGB(255, 255, 255));
+            glyph_cache.set_color(Color::RGB(0, 0, 0));
+        } else {
+            canvas.set_draw_color(Color::RGB(40, 40, 40));
+            glyph_cache.set_color(Color::RGB(255, 255, 255));
+        }
+        canvas.fill_rect(Rect::new(0, y, width, font_size.1)).unwrap();
+        render_text(canvas, glyph_cache, font_size, entry, 3, y);
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        },
+    };
+
     let sdl_context = sdl2::init().expect("Failed to initialize SDL");
     let video_subsystem = sdl_context.video().expect("Failed to initialize video subsystem");
-    let timer_subsystem = sdl_context.timer().expect("Failed to initialize timer subsystem");
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string()).expect("Failed to initialize TTF");
 
     let window = video_subsystem
@@ -82,17 +95,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let font = ttf_context.load_font("font.ttf", 15).expect("Failed to load font.");
 
     let font_size = font.size_of("W")?;
-    let mut cursor = Cursor::new(font_size);
-
-    let (mut glyph_atlas, mapping) = create_glyph_atlas(&mut canvas, &texture_creator, &font, font_size);
+    let mut editor = Editor::new(font_size);
+    editor.registers.clipboard = Some(video_subsystem.clipboard());
+
+    if let Some(path) = keymap::config_path() {
+        if let Ok(config) = std::fs::read_to_string(&path) {
+            // Only the first error fits in the status area
+            if let Some(error) = editor.keymap.apply_config(&config).first() {
+                editor.message = Some(format!("{}: {}", path.display(), error));
+            }
+        }
+    }
 
-    let mut event_pump = sdl_context.event_pump().expect("Failed to set up event pump.");
+    editor.open_args(&args, std::io::stdin());
 
-    // TODO: This should be extracted, no clue how yet
-    let mut render_file_path_input = false;
-    let mut file_path_input_pt = PieceTable::new();
+    let mut glyph_cache = GlyphCache::new(&texture_creator, &font, font_size);
 
-    let mut buffer = Buffer::new();
+    let mut event_pump = sdl_context.event_pump().expect("Failed to set up event pump.");
 
     let background_color = Color {r: 0, g: 0, b: 0, a: 255};
 
@@ -100,262 +119,158 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     text_input_util.stop();
 
     'running: loop { 
-        let start_ticks = timer_subsystem.ticks();
-        // TODO: Only read this again when there are changes
-        let content = buffer.pt.read();
+        // The last two rows are used for the status line and messages
+        let visible_lines = (canvas.viewport().height() / font_size.1).saturating_sub(2).max(1);
+        let visible_columns = canvas.viewport().width() / font_size.0;
+        editor.update_viewport(visible_lines, visible_columns);
 
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    if text_input_util.is_active() {
-                        text_input_util.stop();
-                    }
-                },
-                Event::KeyDown { 
-                    keycode: Some(Keycode::Left),
-                    ..
-                } =>  {
-                    if cursor.index != 0 {
-                        cursor.index -= 1;
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::Right),
-                    ..
-                } => {
-                    cursor.index += 1;
-                    if cursor.index >= content.len() as u32 {
-                        cursor.index = content.len() as u32;
-                    } 
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::Up),
-                    ..
-                } => {
-                    if let Some(index_diff) = Cursor::calc_new_index(&cursor, &content, -1) {
-                        cursor.index -= index_diff;
-                    }
-                },
-                Event::KeyDown {
-                   keycode: Some(Keycode::Down),
-                    ..
-                } => {
-                    if let Some(index_diff) = Cursor::calc_new_index(&cursor, &content, 1) {
-                        cursor.index += index_diff;
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::Backspace),
-                    ..
-                } => {
-                    if render_file_path_input {
-                        let data = &file_path_input_pt.read();
-                        if data.len() > 0 {
-                            file_path_input_pt.delete(data.len() as u32 - 1, 1);
-                        }
-                        continue;
-                    }
-
-                    if !text_input_util.is_active() {
-                        if cursor.index != 0 {
-                            cursor.index -= 1;
-                        }
-                    } else {
-                        if cursor.index != 0 {
-                            if !buffer.pt.delete(cursor.index - 1, 1) {
-                                println!("Failed to delete character ({})", cursor.index - 1);
-                            } else if cursor.index != 0 {
-                                cursor.index -= 1;
-                            }
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::Delete),
-                    ..
-                } => {
-                    if text_input_util.is_active() {
-                        if cursor.index < content.len() as u32 {
-                            if !buffer.pt.delete(cursor.index, 1) {
-                                println!("Failed to delete character ({})", cursor.index);
-                            } 
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::Return),
-                    ..
-                } => {
-                    if render_file_path_input {
-                        // TODO: don't just overwrite current content
-                        // Either create some buffer system or (prompt for) save
-                        if let Ok(content) = read_file(&file_path_input_pt.read()) {
-                            buffer.pt = PieceTable::init(content);
-                            cursor.index = 0;
-                        }
-                        render_file_path_input = false;
-                        text_input_util.stop();
-                        continue;
-                    }
-
-                    if text_input_util.is_active() {
-                        if !buffer.pt.insert("\n", cursor.index) {
-                            println!("Failed to insert newline at index: {}", cursor.index);
-                        } else {
-                            cursor.index += 1;
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keymod: sdl2::keyboard::Mod::LCTRLMOD,
-                    keycode: Some(Keycode::O),
-                    ..
-                } => {
-                    // file picker -> type file path at the bottom of the window
-                    render_file_path_input = true;
-                    file_path_input_pt = PieceTable::new();
-                    text_input_util.start();
-                },
-                Event::TextInput { text, .. } => {
-                    if render_file_path_input {
-                        file_path_input_pt.append(&text);
-                        continue;
-                    }
-
-                    // TODO: This should use the last piece as long as possible
-                    // Just expand the length and keep adding onto the add buffer until another
-                    // piece has been added
-                    if !buffer.pt.insert(&text, cursor.index) {
-                        println!("Write denied ({} at index: {})", &text, cursor.index);
-                    } else {
-                        cursor.index += text.len() as u32;
-                    }
-                },
-                Event::KeyDown { 
-                    keycode: Some(Keycode::I),
-                    ..
-                } => {
-                    if !text_input_util.is_active() {
-                        text_input_util.start();
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::H),
-                    ..
-                } => {
-                    if !text_input_util.is_active() {
-                        if cursor.index != 0 {
-                            cursor.index -= 1;
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::L),
-                    ..
-                } => {
-                    if !text_input_util.is_active() {
-                        cursor.index += 1;
-                        if cursor.index >= content.len() as u32 {
-                            cursor.index = content.len() as u32;
-                        } 
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::J),
-                    ..
-                } => {
-                    if !text_input_util.is_active() {
-                        if let Some(index_diff) = Cursor::calc_new_index(&cursor, &content, 1) {
-                            cursor.index += index_diff;
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::K),
-                    ..                    
-                } => {
-                    if !text_input_util.is_active() {
-                        if let Some(index_diff) = Cursor::calc_new_index(&cursor, &content, -1) {
-                            cursor.index -= index_diff;
-                        }
-                    }
-                },
-                Event::KeyDown {
-                    keycode: Some(Keycode::X),
-                    ..
-                } => {
-                    if cursor.index < content.len() as u32 {
-                        if !buffer.pt.delete(cursor.index, 1) {
-                            println!("Failed to delete character ({})", cursor.index);
-                        }
-                    }
-                },
+                Event::Quit { .. } => editor.request_quit(),
+                Event::KeyDown { keycode: Some(keycode), keymod, .. } => editor.handle_key(keycode, keymod),
+                Event::TextInput { text, .. } => editor.handle_text(&text),
+                Event::MouseWheel { y, .. } => editor.handle_scroll(y),
                 _ => {}
             }
+
+            if editor.quit {
+                break 'running;
+            }
+
+            if editor.wants_text_input() != text_input_util.is_active() {
+                if editor.wants_text_input() {
+                    text_input_util.start();
+                } else {
+                    text_input_util.stop();
+                }
+            }
         }
 
+        // Switching buffers during the events leaves the new buffer with an old size
+        editor.update_viewport(visible_lines, visible_columns);
+
+        let buffers = &editor.buffers;
+        let buffer = buffers.current();
 
         canvas.set_draw_color(background_color);
         canvas.clear();
 
-        glyph_atlas.set_color_mod(255, 255, 255);
-        glyph_atlas.set_blend_mode(sdl2::render::BlendMode::Blend);
-        let mut line = 0;
-        let mut carriage = 0;
-        for c in content.chars() {
-            if c == '\n' {
-                line += 1;
-                carriage = 0;
-                continue;
+        let (cursor_line, _) = buffer.pt.offset_to_line_col(editor.cursor.index);
+        let text_x = (buffer.viewport.gutter * font_size.0) as i32;
+        let rows = buffer.viewport.rows(&buffer.pt);
+        for (row, screen_row) in rows.iter().enumerate() {
+            let y = row as i32 * font_size.1 as i32;
+
+            // Rows that continue a wrapped line get no number
+            if buffer.viewport.gutter > 0 && (!buffer.viewport.wrap || screen_row.first_column == 0) {
+                let color = if screen_row.line == cursor_line { Color::RGB(220, 220, 220) } else { Color::RGB(110, 110, 110) };
+                glyph_cache.set_color(color);
+                let number = buffer.viewport.line_number(screen_row.line, cursor_line);
+                render_text(&mut canvas, &mut glyph_cache, font_size, &number, 0, y);
             }
 
-            let pos = mapping[c as usize];
-            let src = Rect::new(pos.0, pos.1, font_size.0, font_size.1);
-            let dst = Rect::new((carriage * font_size.0) as i32, (font_size.1 * line) as i32, font_size.0, font_size.1);
-            carriage+=1;
-            canvas.copy(&glyph_atlas, Some(src), Some(dst)).unwrap();
-        };
-
-        if render_file_path_input {
-            render_text(&mut canvas, &mut glyph_atlas, mapping, font_size, &String::from("Open file:"), 3, 878 - font_size.1 as i32);
+            glyph_cache.set_color(Color::RGB(255, 255, 255));
+            render_text(&mut canvas, &mut glyph_cache, font_size, &buffer.pt.slice(screen_row.start, screen_row.end), text_x, y);
+        }
 
-            canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
-            let file_picker_bg = Rect::new(0, 880, 1920, font_size.1 + 10);
-            canvas.fill_rect(file_picker_bg).unwrap();
+        if let Some(query) = editor.search_query() {
+            if let (Some(first), Some(last)) = (rows.first(), rows.last()) {
+                let matches: Vec<(u32, u32)> = search::matches(&buffer.pt, &query, first.start, last.end)
+                    .into_iter()
+                    .map(|start| (start, start + query.len() as u32))
+                    .collect();
+                editor.cursor.render_ranges(&mut canvas, &buffer.pt, &buffer.viewport, &rows, &matches, Color::RGBA(255, 200, 0, 90));
+            }
+        }
 
-            glyph_atlas.set_color_mod(0, 0, 0);
-            glyph_atlas.set_blend_mode(sdl2::render::BlendMode::Blend);
-            render_text(&mut canvas, &mut glyph_atlas, mapping, font_size, &file_path_input_pt.read(), 3, 885);
+        if let Some(replacement) = editor.pending_replacement() {
+            let current = [(replacement.start, replacement.end)];
+            editor.cursor.render_ranges(&mut canvas, &buffer.pt, &buffer.viewport, &rows, &current, Color::RGBA(255, 80, 0, 120));
         }
 
-        cursor.render(&mut canvas, &content);
+        if editor.mode.is_visual() {
+            editor.cursor.render_selection(&mut canvas, &buffer.pt, &buffer.viewport, &rows, editor.mode == Mode::VisualLine);
+        }
+        for cursor in editor.cursors.iter_mut() {
+            cursor.render(&mut canvas, &buffer.pt, &buffer.viewport);
+        }
+        editor.cursor.render(&mut canvas, &buffer.pt, &buffer.viewport);
 
         let canvas_width = canvas.viewport().width();
         let canvas_height = canvas.viewport().height();
 
-        let width = font_size.0 * 6;
-        let height = font_size.1;
-        let x = canvas_width - width;
-        let y = canvas_height - height;
-
-        let text_mode = if text_input_util.is_active() {
-            "insert"
-        } else {
-            "normal"
+        let message_y = canvas_height.saturating_sub(font_size.1) as i32;
+        let status_y = message_y - font_size.1 as i32;
+
+        let status_line = StatusLine::new(&editor);
+        canvas.set_draw_color(Color::RGB(60, 60, 60));
+        canvas.fill_rect(Rect::new(0, status_y, canvas_width, font_size.1)).unwrap();
+        glyph_cache.set_color(Color::RGB(255, 255, 255));
+        render_text(&mut canvas, &mut glyph_cache, font_size, &status_line.left, 0, status_y);
+        let right_x = canvas_width as i32 - (status_line.right.chars().count() as u32 * font_size.0) as i32;
+        render_text(&mut canvas, &mut glyph_cache, font_size, &status_line.right, right_x, status_y);
+
+        // Prompts take over the message row, messages are then shown at its end
+        let input = editor.prompt_input.read();
+        let prompt = match editor.mode {
+            Mode::Command => Some(format!(":{}", input)),
+            Mode::Prompt(Prompt::FilePath(prompt)) => Some(format!("{} {}", prompt.label(), input)),
+            Mode::Prompt(Prompt::Search(direction)) => Some(format!("{}{}", direction.prefix(), input)),
+            Mode::Prompt(Prompt::ConfirmSubstitute) => editor.pending_replacement()
+                .map(|replacement| format!("replace with {} (y/n/a/q/l)?", replacement.text)),
+            Mode::Prompt(Prompt::UnsavedChanges(_)) => Some(format!("{} has unsaved changes: (s)ave, (d)iscard, (c)ancel", buffer.display_name())),
+            Mode::Prompt(Prompt::FilePicker) => editor.file_picker.as_ref().map(|picker| match &picker.error {
+                Some(error) => error.clone(),
+                None => format!("{}/ > {} ({} matches)", picker.dir.display(), picker.query, picker.match_count()),
+            }),
+            _ => None,
         };
+        match (prompt, &editor.message) {
+            (Some(prompt), message) => {
+                render_bar(&mut canvas, &mut glyph_cache, font_size, &prompt, message_y);
+                if let Some(message) = message {
+                    let x = canvas_width as i32 - (message.chars().count() as u32 * font_size.0) as i32;
+                    render_text(&mut canvas, &mut glyph_cache, font_size, message, x, message_y);
+                }
+            },
+            (None, Some(message)) => {
+                glyph_cache.set_color(Color::RGB(255, 255, 255));
+                render_text(&mut canvas, &mut glyph_cache, font_size, message, 0, message_y);
+            },
+            (None, None) => {},
+        }
+
+        if let Mode::Prompt(Prompt::BufferPicker(selected)) = editor.mode {
+            let entries: Vec<String> = buffers.iter().enumerate().map(|(i, b)| {
+                let current = if i == buffers.current_index() { "%" } else { " " };
+                let modified = if b.is_modified() { " [+]" } else { "" };
+                format!("{current}{} {}{modified}", i + 1, b.display_name())
+            }).collect();
+            render_list(&mut canvas, &mut glyph_cache, font_size, &entries, Some(selected), status_y);
+        }
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        render_text(&mut canvas, &mut glyph_atlas, mapping, font_size, &String::from(text_mode), x as i32, y as i32);
+        if let Some(picker) = &editor.file_picker {
+            // Only a page of the matches is shown, scrolled so the selected one is on it
+            let page = ((status_y / font_size.1 as i32) as usize).clamp(1, 20);
+            let first = picker.selected.saturating_sub(page - 1);
+            let entries: Vec<String> = picker.matches().skip(first).take(page).map(|entry| entry.display()).collect();
+            render_list(&mut canvas, &mut glyph_cache, font_size, &entries, Some(picker.selected - first), status_y);
+        }
+
+        if !editor.completions.is_empty() {
+            // Only the last part of the path, the rest is the same for all of them
+            let entries: Vec<String> = editor.completions.iter().map(|completion| {
+                let name = completion.trim_end_matches('/').rsplit('/').next().unwrap_or(completion);
+                let slash = if completion.ends_with('/') { "/" } else { "" };
+                format!("{}{}", name, slash)
+            }).collect();
+            render_list(&mut canvas, &mut glyph_cache, font_size, &entries, editor.completion_index, status_y);
+        }
 
         canvas.present();
 
-        let frame_time = timer_subsystem.ticks() - start_ticks;
-        let fps = 1000.0 / frame_time as f32;
-        canvas.window_mut().set_title(&format!("awildtxt - FPS: {fps}"))?;
+        let modified = if buffer.is_modified() { " [+]" } else { "" };
+        canvas.window_mut().set_title(&format!("awildtxt - {}{modified}", buffer.display_name()))?;
     }
     Ok(())
 }
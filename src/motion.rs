@@ -0,0 +1,385 @@
+use crate::{cursor::Cursor, piece_table::PieceTable};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    FindChar(char),
+    TillChar(char),
+}
+
+/// How the text between the cursor and the end of a motion is operated on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionKind {
+    // Up to but not including the end
+    Exclusive,
+    // Including the character at the end
+    Inclusive,
+    // Every line from the start to the end
+    Linewise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+pub fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// The character starting at `index` and the index after it
+pub fn char_at(pt: &PieceTable, index: u32) -> Option<(char, u32)> {
+    if index >= pt.len() {
+        return None;
+    }
+
+    let mut end = index + 1;
+    while !pt.is_char_boundary(end) {
+        end += 1;
+    }
+    pt.slice(index, end).chars().next().map(|c| (c, end))
+}
+
+/// The character ending at `index` and the index it starts at
+fn char_before(pt: &PieceTable, index: u32) -> Option<(char, u32)> {
+    if index == 0 {
+        return None;
+    }
+
+    let mut start = index - 1;
+    while !pt.is_char_boundary(start) {
+        start -= 1;
+    }
+    pt.slice(start, index).chars().next().map(|c| (c, start))
+}
+
+fn class_at(pt: &PieceTable, index: u32) -> Option<CharClass> {
+    char_at(pt, index).map(|(c, _)| class(c))
+}
+
+/// Start of the next word, empty lines count as words. With `stop_at_newline` a word at the end
+/// of a line ends there instead of at the start of the next line, like vim does for operators.
+fn word_forward(pt: &PieceTable, index: u32, stop_at_newline: bool) -> u32 {
+    let mut index = index;
+    let start_class = match class_at(pt, index) {
+        Some(c) => c,
+        None => return index,
+    };
+
+    if start_class != CharClass::Space {
+        while let Some((c, next)) = char_at(pt, index) {
+            if class(c) != start_class {
+                break;
+            }
+            index = next;
+        }
+    }
+
+    while let Some((c, next)) = char_at(pt, index) {
+        if !c.is_whitespace() {
+            break;
+        }
+        if c == '\n' {
+            if stop_at_newline && start_class != CharClass::Space {
+                break;
+            }
+            if let Some(('\n', _)) = char_at(pt, next) {
+                return next;
+            }
+        }
+        index = next;
+    }
+
+    index
+}
+
+fn word_backward(pt: &PieceTable, index: u32) -> u32 {
+    let mut index = index;
+    while let Some((c, start)) = char_before(pt, index) {
+        if !c.is_whitespace() {
+            break;
+        }
+        // Stop on empty lines
+        if c == '\n' && matches!(char_before(pt, start), Some(('\n', _))) {
+            return start;
+        }
+        index = start;
+    }
+
+    let word_class = match char_before(pt, index) {
+        Some((c, _)) => class(c),
+        None => return index,
+    };
+    while let Some((c, start)) = char_before(pt, index) {
+        if class(c) != word_class {
+            break;
+        }
+        index = start;
+    }
+
+    index
+}
+
+/// Last character of the word, when `stay` is set a cursor already on the last character stays
+fn word_end(pt: &PieceTable, index: u32, stay: bool) -> u32 {
+    let mut index = index;
+    if !stay {
+        match char_at(pt, index) {
+            Some((_, next)) => index = next,
+            None => return index,
+        }
+    }
+
+    while let Some((c, next)) = char_at(pt, index) {
+        if !c.is_whitespace() {
+            break;
+        }
+        index = next;
+    }
+
+    let word_class = match class_at(pt, index) {
+        Some(c) => c,
+        // Nothing but whitespace until the end of the buffer
+        None => return char_before(pt, index).map_or(index, |(_, start)| start),
+    };
+    while let Some((_, next)) = char_at(pt, index) {
+        if class_at(pt, next) != Some(word_class) {
+            break;
+        }
+        index = next;
+    }
+
+    index
+}
+
+/// The `count`th occurrence of `target` after `index` on the same line
+fn find_char(pt: &PieceTable, index: u32, target: char, count: u32) -> Option<u32> {
+    let mut found = 0;
+    let mut index = char_at(pt, index)?.1;
+    while let Some((c, next)) = char_at(pt, index) {
+        if c == '\n' {
+            return None;
+        }
+        if c == target {
+            found += 1;
+            if found == count {
+                return Some(index);
+            }
+        }
+        index = next;
+    }
+
+    None
+}
+
+/// Takes `step` `repeat` times, the step number is passed along. Stops early once a step no longer
+/// moves, so huge counts end at the start or end of the buffer.
+fn repeat_step(index: u32, repeat: u32, mut step: impl FnMut(u32, u32) -> u32) -> u32 {
+    let mut index = index;
+    for n in 0..repeat {
+        let next = step(index, n);
+        if next == index {
+            break;
+        }
+        index = next;
+    }
+
+    index
+}
+
+fn line_of(pt: &PieceTable, index: u32) -> u32 {
+    pt.offset_to_line_col(index).0
+}
+
+fn line_end(pt: &PieceTable, line: u32) -> u32 {
+    pt.line_start(line).unwrap_or(0) + pt.line_len(line).unwrap_or(0)
+}
+
+/// Index of the first character on `line` that is not a space or tab
+pub fn first_non_blank(pt: &PieceTable, line: u32) -> u32 {
+    let start = pt.line_start(line).unwrap_or(0);
+    let text = pt.slice(start, line_end(pt, line));
+    start + (text.len() - text.trim_start().len()) as u32
+}
+
+impl Motion {
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine => MotionKind::Linewise,
+            Motion::WordEnd | Motion::FindChar(_) | Motion::TillChar(_) => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+
+    /// Where the motion ends when started at `index`, `None` when it can't be made. For `gg` and
+    /// `G` the count is a line number, for the other motions it repeats them. `for_operator`
+    /// applies the special cases vim has for motions used with an operator.
+    pub fn apply(&self, pt: &PieceTable, index: u32, count: Option<u32>, for_operator: bool) -> Option<u32> {
+        let repeat = count.unwrap_or(1).max(1);
+        let line = line_of(pt, index);
+        let last_line = pt.line_count() - 1;
+
+        match *self {
+            Motion::Left => Some(repeat_step(index, repeat, |i, _| pt.prev_grapheme_boundary(i))),
+            Motion::Right => Some(repeat_step(index, repeat, |i, _| pt.next_grapheme_boundary(i))),
+            Motion::Up | Motion::Down => {
+                let target = if *self == Motion::Up {
+                    line.checked_sub(1)?.saturating_sub(repeat - 1)
+                } else if line < last_line {
+                    (line + repeat).min(last_line)
+                } else {
+                    return None;
+                };
+                Cursor::index_at_column(pt, target, Cursor::column(pt, index))
+            },
+            Motion::WordForward => {
+                Some(repeat_step(index, repeat, |i, n| word_forward(pt, i, for_operator && n == repeat - 1)))
+            },
+            Motion::WordBackward => Some(repeat_step(index, repeat, |i, _| word_backward(pt, i))),
+            Motion::WordEnd => Some(repeat_step(index, repeat, |i, _| word_end(pt, i, false))),
+            Motion::LineStart => pt.line_start(line),
+            Motion::LineEnd => Some(line_end(pt, (line + repeat - 1).min(last_line))),
+            Motion::FirstLine | Motion::LastLine => {
+                let target = match count {
+                    Some(count) => count.saturating_sub(1).min(last_line),
+                    None if *self == Motion::FirstLine => 0,
+                    None => last_line,
+                };
+                pt.line_start(target)
+            },
+            Motion::FindChar(c) => find_char(pt, index, c, repeat),
+            Motion::TillChar(c) => {
+                let found = find_char(pt, index, c, repeat)?;
+                Some(char_before(pt, found)?.1)
+            },
+        }
+    }
+}
+
+/// `cw` changes to the end of the word instead of to the start of the next one, like `ce` but a
+/// cursor on the last character of a word only changes that character
+pub fn change_word_end(pt: &PieceTable, index: u32, count: Option<u32>) -> u32 {
+    let repeat = count.unwrap_or(1).max(1);
+    repeat_step(word_end(pt, index, true), repeat - 1, |i, _| word_end(pt, i, false))
+}
+
+/// The byte range an operator works on for a motion from `from` to `to`. Linewise ranges hold
+/// whole lines including their line break.
+pub fn range(pt: &PieceTable, from: u32, to: u32, kind: MotionKind) -> (u32, u32) {
+    let (start, end) = (from.min(to), from.max(to));
+    match kind {
+        MotionKind::Exclusive => (start, end),
+        MotionKind::Inclusive => (start, pt.next_grapheme_boundary(end)),
+        MotionKind::Linewise => {
+            let first = line_of(pt, start);
+            let last = line_of(pt, end);
+            (pt.line_start(first).unwrap_or(0), pt.line_start(last + 1).unwrap_or(pt.len()))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::motion::{change_word_end, range, Motion, MotionKind};
+    use crate::piece_table::PieceTable;
+
+    fn apply(text: &str, motion: Motion, index: u32, count: Option<u32>) -> Option<u32> {
+        Motion::apply(&motion, &PieceTable::init(String::from(text)), index, count, false)
+    }
+
+    #[test]
+    fn word_motions() {
+        let text = "foo.bar  baz\n\n  qux";
+        assert_eq!(apply(text, Motion::WordForward, 0, None), Some(3));
+        assert_eq!(apply(text, Motion::WordForward, 3, None), Some(4));
+        assert_eq!(apply(text, Motion::WordForward, 4, None), Some(9));
+        // Empty lines are words too
+        assert_eq!(apply(text, Motion::WordForward, 9, None), Some(13));
+        assert_eq!(apply(text, Motion::WordForward, 13, None), Some(16));
+        assert_eq!(apply(text, Motion::WordForward, 0, Some(3)), Some(9));
+
+        assert_eq!(apply(text, Motion::WordBackward, 16, None), Some(13));
+        assert_eq!(apply(text, Motion::WordBackward, 13, None), Some(9));
+        assert_eq!(apply(text, Motion::WordBackward, 10, None), Some(9));
+        assert_eq!(apply(text, Motion::WordBackward, 9, Some(2)), Some(3));
+
+        assert_eq!(apply(text, Motion::WordEnd, 0, None), Some(2));
+        assert_eq!(apply(text, Motion::WordEnd, 2, None), Some(3));
+        assert_eq!(apply(text, Motion::WordEnd, 9, None), Some(11));
+        assert_eq!(apply(text, Motion::WordEnd, 11, None), Some(18));
+    }
+
+    #[test]
+    fn word_forward_for_operator_stops_at_line_end() {
+        let pt = PieceTable::init(String::from("foo bar\nbaz"));
+        assert_eq!(Motion::WordForward.apply(&pt, 4, None, true), Some(7));
+        assert_eq!(Motion::WordForward.apply(&pt, 0, Some(2), true), Some(7));
+        assert_eq!(Motion::WordForward.apply(&pt, 4, None, false), Some(8));
+    }
+
+    #[test]
+    fn change_word_stays_in_word() {
+        let pt = PieceTable::init(String::from("foo bar"));
+        assert_eq!(change_word_end(&pt, 0, None), 2);
+        assert_eq!(change_word_end(&pt, 2, None), 2);
+        assert_eq!(change_word_end(&pt, 0, Some(2)), 6);
+    }
+
+    #[test]
+    fn line_motions() {
+        let text = "one\ntwo\nthree";
+        assert_eq!(apply(text, Motion::LineStart, 6, None), Some(4));
+        assert_eq!(apply(text, Motion::LineEnd, 4, None), Some(7));
+        assert_eq!(apply(text, Motion::LineEnd, 0, Some(2)), Some(7));
+        assert_eq!(apply(text, Motion::FirstLine, 10, None), Some(0));
+        assert_eq!(apply(text, Motion::LastLine, 1, None), Some(8));
+        assert_eq!(apply(text, Motion::LastLine, 1, Some(2)), Some(4));
+        assert_eq!(apply(text, Motion::Down, 1, Some(5)), Some(9));
+        assert_eq!(apply(text, Motion::Up, 1, None), None);
+    }
+
+    #[test]
+    fn find_and_till_stay_on_line() {
+        let text = "a,b,c\nd,e";
+        assert_eq!(apply(text, Motion::FindChar(','), 0, None), Some(1));
+        assert_eq!(apply(text, Motion::FindChar(','), 0, Some(2)), Some(3));
+        assert_eq!(apply(text, Motion::FindChar(','), 0, Some(3)), None);
+        assert_eq!(apply(text, Motion::TillChar('c'), 0, None), Some(3));
+        assert_eq!(apply(text, Motion::FindChar('e'), 0, None), None);
+    }
+
+    #[test]
+    fn multibyte_words() {
+        let text = "h\u{e9}llo w\u{f6}rld";
+        assert_eq!(apply(text, Motion::WordForward, 0, None), Some(7));
+        assert_eq!(apply(text, Motion::WordEnd, 0, None), Some(5));
+        assert_eq!(apply(text, Motion::WordBackward, 13, None), Some(7));
+    }
+
+    #[test]
+    fn operator_ranges() {
+        let pt = PieceTable::init(String::from("one\ntwo\nthree"));
+        assert_eq!(range(&pt, 5, 1, MotionKind::Exclusive), (1, 5));
+        assert_eq!(range(&pt, 1, 5, MotionKind::Inclusive), (1, 6));
+        assert_eq!(range(&pt, 5, 1, MotionKind::Linewise), (0, 8));
+        assert_eq!(range(&pt, 9, 9, MotionKind::Linewise), (8, 13));
+    }
+}
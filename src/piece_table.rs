@@ -1,22 +1,47 @@
+use std::collections::VecDeque;
+
+use unicode_segmentation::GraphemeCursor;
+
+use crate::piece_tree::PieceTree;
+
+// Edits further back than this are dropped from the undo history
+const MAX_UNDO_LEVELS: usize = 1000;
+
+// Bytes to either side of an offset looked at first when searching for a grapheme boundary
+const GRAPHEME_WINDOW: u32 = 64;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Piece {
-    is_add: bool,
+    pub is_add: bool,
+    pub offset: u32,
+    pub length: u32,
+    pub line_breaks: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Revision {
+    pieces: PieceTree,
+    // Offset of the edit, used to put the cursor back where the change happened
     offset: u32,
-    length: u32
+    id: u64,
 }
 
 #[derive(Debug, Clone)]
 pub struct PieceTable {
     ro_buffer: String,
     add_buffer: String,
-    pub pieces: Vec<Piece>
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct PieceSearch {
-    piece: Piece,
-    index: u32,
-    piece_start: u32,
+    pieces: PieceTree,
+    // Sorted offsets of every '\n' in the buffers, used to count line breaks in a piece
+    ro_newlines: Vec<u32>,
+    add_newlines: Vec<u32>,
+    undo_stack: VecDeque<Revision>,
+    redo_stack: Vec<Revision>,
+    undo_group_open: bool,
+    undo_group_recorded: bool,
+    // Every state of the content gets a unique id, so undoing back to the saved state is not modified
+    revision: u64,
+    next_revision: u64,
+    saved_revision: u64,
 }
 
 impl PieceTable {
@@ -24,257 +49,391 @@ impl PieceTable {
         PieceTable {
             ro_buffer: String::new(),
             add_buffer: String::new(),
-            pieces: Vec::new(),
+            pieces: PieceTree::new(),
+            ro_newlines: Vec::new(),
+            add_newlines: Vec::new(),
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            undo_group_open: false,
+            undo_group_recorded: false,
+            revision: 0,
+            next_revision: 1,
+            saved_revision: 0,
         }
     }
 
     pub fn init(base_content: String) -> Self {
         let mut pt = PieceTable {
-            ro_buffer: base_content.clone(),
-            add_buffer: String::new(),
-            pieces: Vec::new()
+            ro_newlines: Self::find_newlines(&base_content, 0),
+            ro_buffer: base_content,
+            ..PieceTable::new()
         };
 
-        pt.pieces.push(Piece {
-            is_add: false,
-            length: base_content.len() as u32,
-            offset: 0,
-        });
+        if !pt.ro_buffer.is_empty() {
+            let p = pt.make_piece(false, 0, pt.ro_buffer.len() as u32);
+            pt.pieces.push(p);
+        }
 
         pt 
     }
 
     pub fn append(&mut self, content: &str) {
-        let p = Piece {
-            is_add: true,
-            offset: self.add_buffer.len() as u32,
-            length: content.len() as u32,
-        };
-        self.pieces.push(p);
-        self.add_buffer.push_str(content);
+        let offset = self.len();
+        self.insert(content, offset);
     }
 
-    fn find_piece_at_offset(pieces: &mut Vec<Piece>, offset: u32) -> Option<PieceSearch> {
-        let mut cursor = 0;
-        for (i, p) in pieces.iter().enumerate() {
-            if cursor + p.length >= offset {
-                return Some(PieceSearch {
-                    piece: *p, 
-                    index: i as u32, 
-                    piece_start: cursor,
-                });
-            }
+    fn find_newlines(content: &str, base_offset: u32) -> Vec<u32> {
+        content.bytes()
+            .enumerate()
+            .filter(|(_, b)| *b == b'\n')
+            .map(|(i, _)| base_offset + i as u32)
+            .collect()
+    }
 
-            cursor += p.length;
+    fn newlines(&self, is_add: bool) -> &[u32] {
+        if is_add {
+            &self.add_newlines
+        } else {
+            &self.ro_newlines
         }
-
-        None
     }
 
-    fn find_pieces_in_range(pieces: &mut Vec<Piece>, offset: u32, length: u32) -> Vec<PieceSearch> {
-        let mut cursor = 0;
-        let mut searches: Vec<PieceSearch> = Vec::new();
+    /// Index into the buffer newlines of the first newline at or after `offset`
+    fn newline_index(&self, is_add: bool, offset: u32) -> usize {
+        self.newlines(is_add).partition_point(|n| *n < offset)
+    }
 
-        for (i, p) in pieces.iter().enumerate() {
-            if cursor + p.length >= offset {
-                searches.push(PieceSearch {
-                    piece: *p, 
-                    index: i as u32, 
-                    piece_start: cursor,
-                });
-                
-                if cursor + p.length >= offset + length {
-                    return searches;
-                }
-            }
+    fn make_piece(&self, is_add: bool, offset: u32, length: u32) -> Piece {
+        let line_breaks = self.newline_index(is_add, offset + length) - self.newline_index(is_add, offset);
 
-            cursor += p.length;
+        Piece {
+            is_add,
+            offset,
+            length,
+            line_breaks: line_breaks as u32,
         }
+    }
 
-        searches
+    fn split_piece(&self, piece: Piece, at: u32) -> (Piece, Piece) {
+        (
+            self.make_piece(piece.is_add, piece.offset, at),
+            self.make_piece(piece.is_add, piece.offset + at, piece.length - at),
+        )
     }
 
-    fn is_offset_valid(pieces: &Vec<Piece>, offset: u32) -> bool {
-        let mut max_offset = 0;
-        for p in pieces.iter() {
-            max_offset += p.length;
+    /// Returns the offset of the content inside the add buffer
+    fn push_add_buffer(&mut self, content: &str) -> u32 {
+        let offset = self.add_buffer.len() as u32;
+        self.add_newlines.extend(Self::find_newlines(content, offset));
+        self.add_buffer.push_str(content);
+        offset
+    }
+
+    fn buffer(&self, is_add: bool) -> &str {
+        if is_add {
+            &self.add_buffer
+        } else {
+            &self.ro_buffer
         }
+    }
 
-        return !offset > max_offset
+    /// Offsets inside a multibyte character are not valid to edit at
+    pub fn is_char_boundary(&self, offset: u32) -> bool {
+        if offset > self.len() {
+            return false;
+        }
+
+        match self.pieces.find_offset(offset) {
+            Some(location) => {
+                let p = location.piece;
+                self.buffer(p.is_add).is_char_boundary((p.offset + offset - location.piece_start) as usize)
+            },
+            None => offset == 0,
+        }
     }
 
     pub fn insert(&mut self, content: &str, offset: u32) -> bool {
-        let pieces = &mut self.pieces;
-
-        // Validate if offset is possible
-        if !Self::is_offset_valid(pieces, offset) {
+        if !self.is_char_boundary(offset) {
             return false;
         }
 
-        // Find the current Piece that matches the offset
-        let search_opt = Self::find_piece_at_offset(pieces, offset);
-
-        match search_opt {
-            Some(piece_search) => {
-                let piece_end = piece_search.piece_start + piece_search.piece.length;
-
-                // Split Piece into 2
-                let piece_one_len = offset - piece_search.piece_start;
-                let piece_two_len = piece_end - offset;
-
-                let mut idx = piece_search.index;
-                // Remove the piece from the pieces
-                pieces.remove(piece_search.index as usize);
-
-                // Create Piece for the first split
-                if piece_one_len > 0 {
-                    let p_one = Piece {
-                        is_add: piece_search.piece.is_add,
-                        offset: piece_search.piece.offset,
-                        length: piece_one_len,
-                    };
-
-                    pieces.insert(idx as usize, p_one);
-                    idx += 1;
-                }
-
-                // Insert new content
-                let new_piece = Piece {
-                    is_add: true,
-                    offset: self.add_buffer.len() as u32,
-                    length: content.len() as u32,
-                };
-                pieces.insert(idx as usize, new_piece);
-                self.add_buffer.push_str(content);
-                idx += 1;
-
-                // Create Piece for the second split
-                if piece_two_len > 0 {
-                    let p_two = Piece {
-                        is_add: piece_search.piece.is_add,
-                        offset: piece_search.piece.offset + piece_one_len,
-                        length: piece_two_len,
-                    };
-                    pieces.insert(idx as usize, p_two);
-                }
-            },
-            None => {
-                if offset > 0 {
-                    return false;
-                }
-
-                self.pieces.insert(0, Piece {
-                    is_add: true,
-                    offset: self.add_buffer.len() as u32,
-                    length: content.len() as u32,
-                });
-                self.add_buffer.push_str(content);
-            }
+        if content.is_empty() {
+            return true;
         }
-         
 
+        let add_offset = self.push_add_buffer(content);
+        let new_piece = self.make_piece(true, add_offset, content.len() as u32);
+
+        // Cloning only copies the root, the tree itself is shared with the previous revision
+        let previous = self.pieces.clone();
+        let mut pieces = previous.clone();
+        pieces.insert(offset, new_piece, &|p, at| self.split_piece(p, at));
+        self.pieces = pieces;
+
+        self.record_revision(previous, offset);
         true
     }
-    
-    pub fn delete(&mut self, offset: u32, length: u32) -> bool {
-        let pieces = &mut self.pieces;
 
-        if !Self::is_offset_valid(pieces, offset) {
-            // Should it delete the last character (-length) if this happens? 
+    pub fn delete(&mut self, offset: u32, length: u32) -> bool {
+        if length == 0 || !self.is_char_boundary(offset) || !self.is_char_boundary(offset + length) {
             return false;
         }
 
-        let mut searches = Self::find_pieces_in_range(pieces, offset, length);
-
-        match searches.len() {
-            n if n >= 2 => {
-                // Remove all the inner pieces and grab the total length 
-                let last = searches.pop().unwrap();
-                let first = searches.swap_remove(0);
-
-                let mut total_length = 0;
-                for s in searches {
-                    total_length += s.piece.length;
-                }
-
-                for idx in (first.index..(last.index + 1)).rev() {
-                    pieces.remove(idx as usize);
-                }
-
-                let rest = length - (first.piece_start + first.piece.length - offset) - total_length;
-                let piece_one_len = offset - first.piece_start;
-                let piece_two_offset = last.piece.offset + rest;
-                let piece_two_len = last.piece.length - rest;
-
-                let mut idx = first.index;
-                if piece_one_len > 0 {
-                    let p_one = Piece {
-                        is_add: first.piece.is_add,
-                        offset: first.piece.offset,
-                        length: piece_one_len,
-                    };
-                    pieces.insert(idx as usize, p_one);
-                    idx += 1;
-                }
-
-                if piece_two_len > 0 {
-                    let p_two = Piece {
-                        is_add: last.piece.is_add,
-                        offset: piece_two_offset,
-                        length: piece_two_len,
-                    };
-                    pieces.insert(idx as usize, p_two);
-                }
-                
-            },
-            1 => {
-                let p = searches[0];
-
-                pieces.remove(p.index as usize);
-
-                let piece_one_len = offset - p.piece_start;
-                let piece_two_len = (p.piece_start + p.piece.length) - (offset + length);
-
-                let mut idx = p.index;
-                if piece_one_len > 0 {
-                    let p_one = Piece {
-                        is_add: p.piece.is_add,
-                        offset: p.piece.offset,
-                        length: piece_one_len,
-                    };
-                    pieces.insert(idx as usize, p_one);
-                    idx += 1;
-                }
-
-                if piece_two_len > 0 {
-                    let p_two = Piece {
-                        is_add: p.piece.is_add,
-                        offset: p.piece.offset + piece_one_len + length,
-                        length: piece_two_len,
-                    };
-                    pieces.insert(idx as usize, p_two);
-                }
-            },
-            _ => {
-                return false;
+        let previous = self.pieces.clone();
+        let mut pieces = previous.clone();
+        pieces.delete(offset, length, &|p, at| self.split_piece(p, at));
+        self.pieces = pieces;
+
+        self.record_revision(previous, offset);
+        true
+    }
+
+    fn record_revision(&mut self, previous: PieceTree, offset: u32) {
+        self.redo_stack.clear();
+
+        let previous_id = self.revision;
+        self.revision = self.next_revision;
+        self.next_revision += 1;
+
+        // Only the state before the first edit of a group is needed to undo the whole group
+        if self.undo_group_open {
+            if self.undo_group_recorded {
+                return;
+            }
+            self.undo_group_recorded = true;
+        }
+
+        if self.undo_stack.len() >= MAX_UNDO_LEVELS {
+            self.undo_stack.pop_front();
+        }
+
+        self.undo_stack.push_back(Revision {
+            pieces: previous,
+            offset,
+            id: previous_id,
+        });
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_revision = self.revision;
+    }
+
+    /// Content that was never saved anywhere, like text read from stdin, stays modified until it is
+    pub fn mark_unsaved(&mut self) {
+        // No revision ever gets this id
+        self.saved_revision = u64::MAX;
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.revision != self.saved_revision
+    }
+
+    /// Every edit until `end_undo_group` is undone and redone as a single step
+    pub fn begin_undo_group(&mut self) {
+        if self.undo_group_open {
+            return;
+        }
+        self.undo_group_open = true;
+        self.undo_group_recorded = false;
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.undo_group_open = false;
+        self.undo_group_recorded = false;
+    }
+
+    /// Returns the offset of the undone edit
+    pub fn undo(&mut self) -> Option<u32> {
+        self.end_undo_group();
+
+        let revision = self.undo_stack.pop_back()?;
+        let current = std::mem::replace(&mut self.pieces, revision.pieces);
+        self.redo_stack.push(Revision {
+            pieces: current,
+            offset: revision.offset,
+            id: self.revision,
+        });
+        self.revision = revision.id;
+
+        Some(revision.offset)
+    }
+
+    /// Returns the offset of the redone edit
+    pub fn redo(&mut self) -> Option<u32> {
+        self.end_undo_group();
+
+        let revision = self.redo_stack.pop()?;
+        let current = std::mem::replace(&mut self.pieces, revision.pieces);
+        self.undo_stack.push_back(Revision {
+            pieces: current,
+            offset: revision.offset,
+            id: self.revision,
+        });
+        self.revision = revision.id;
+
+        Some(revision.offset)
+    }
+
+    pub fn len(&self) -> u32 {
+        self.pieces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn line_count(&self) -> u32 {
+        self.pieces.line_breaks() + 1
+    }
+
+    /// Offset of the first character of `line`
+    pub fn line_start(&self, line: u32) -> Option<u32> {
+        if line == 0 {
+            return Some(0);
+        }
+
+        // Looking for the end of the previous line
+        let location = self.pieces.find_line_break(line - 1)?;
+        let p = location.piece;
+        let first = self.newline_index(p.is_add, p.offset);
+        let newline = self.newlines(p.is_add)[first + (line - 1 - location.line_breaks_before) as usize];
+
+        Some(location.piece_start + (newline - p.offset) + 1)
+    }
+
+    /// Length of `line` without its line break
+    pub fn line_len(&self, line: u32) -> Option<u32> {
+        let start = self.line_start(line)?;
+        let end = match self.line_start(line + 1) {
+            Some(next) => next - 1,
+            None => self.len(),
+        };
+
+        Some(end - start)
+    }
+
+    pub fn offset_to_line_col(&self, offset: u32) -> (u32, u32) {
+        let offset = offset.min(self.len());
+
+        let line = match self.pieces.find_offset(offset) {
+            Some(location) => {
+                let p = location.piece;
+                let end = p.offset + (offset - location.piece_start);
+                let breaks = self.newline_index(p.is_add, end) - self.newline_index(p.is_add, p.offset);
+                location.line_breaks_before + breaks as u32
             },
+            None => 0,
         };
 
-        true
+        let line_start = self.line_start(line).unwrap_or(0);
+        (line, offset - line_start)
     }
 
-    pub fn read(&mut self) -> String {
-        let mut s: String = String::new();
-        for p in self.pieces.iter() {
-            let buffer = if p.is_add {
-                &self.add_buffer
+    /// Columns past the end of the line are clamped to the end of the line
+    pub fn line_col_to_offset(&self, line: u32, col: u32) -> Option<u32> {
+        let start = self.line_start(line)?;
+        let len = self.line_len(line)?;
+
+        Some(start + col.min(len))
+    }
+
+    /// Iterates over the text between `start` and `end` without copying it out of the buffers
+    pub fn chunks(&self, start: u32, end: u32) -> impl Iterator<Item = &str> {
+        let end = end.min(self.len());
+        let (pieces, piece_start) = self.pieces.iter_from(start);
+
+        pieces
+            .scan(piece_start, |piece_start, p| {
+                let location = (*piece_start, p);
+                *piece_start += p.length;
+                Some(location)
+            })
+            .take_while(move |(piece_start, _)| *piece_start < end)
+            .map(move |(piece_start, p)| {
+                let from = p.offset + start.saturating_sub(piece_start);
+                let to = p.offset + (end - piece_start).min(p.length);
+                &self.buffer(p.is_add)[(from as usize)..(to as usize)]
+            })
+    }
+
+    /// Like `chunks` but from `end` back to `start`, along with the offset each chunk starts at
+    pub fn chunks_rev(&self, start: u32, end: u32) -> impl Iterator<Item = (u32, &str)> {
+        let mut end = end.min(self.len());
+
+        std::iter::from_fn(move || {
+            if end <= start {
+                return None;
+            }
+
+            // An offset on the boundary of two pieces finds the one that ends there
+            let location = self.pieces.find_offset(end)?;
+            let p = location.piece;
+            let chunk_start = location.piece_start.max(start);
+            let from = p.offset + chunk_start - location.piece_start;
+            let to = p.offset + end - location.piece_start;
+            end = chunk_start;
+            Some((chunk_start, &self.buffer(p.is_add)[(from as usize)..(to as usize)]))
+        })
+    }
+
+    pub fn slice(&self, start: u32, end: u32) -> String {
+        self.chunks(start, end).collect()
+    }
+
+    /// Text `radius` bytes to either side of `offset`, widened to the nearest char boundaries
+    fn window(&self, offset: u32, radius: u32) -> (u32, String) {
+        let mut start = offset.saturating_sub(radius);
+        while start > 0 && !self.is_char_boundary(start) {
+            start -= 1;
+        }
+
+        let mut end = offset.saturating_add(radius).min(self.len());
+        while end < self.len() && !self.is_char_boundary(end) {
+            end += 1;
+        }
+
+        (start, self.slice(start, end))
+    }
+
+    /// Only a small window around the offset is looked at, it is widened when the grapheme cursor
+    /// needs more text than the window holds
+    fn grapheme_boundary(&self, offset: u32, forward: bool) -> u32 {
+        let mut radius = GRAPHEME_WINDOW;
+        loop {
+            let (start, text) = self.window(offset, radius);
+            let mut cursor = GraphemeCursor::new(offset as usize, self.len() as usize, true);
+            let boundary = if forward {
+                cursor.next_boundary(&text, start as usize)
             } else {
-                &self.ro_buffer
+                cursor.prev_boundary(&text, start as usize)
             };
 
-            s.push_str(&buffer[(p.offset as usize)..(p.offset as usize + p.length as usize)]);
+            match boundary {
+                Ok(Some(boundary)) => return boundary as u32,
+                Ok(None) => return if forward { self.len() } else { 0 },
+                Err(_) => radius = radius.saturating_mul(2),
+            }
+        }
+    }
+
+    pub fn next_grapheme_boundary(&self, offset: u32) -> u32 {
+        if offset >= self.len() {
+            return self.len();
+        }
+
+        self.grapheme_boundary(offset, true)
+    }
+
+    pub fn prev_grapheme_boundary(&self, offset: u32) -> u32 {
+        if offset == 0 {
+            return 0;
         }
-        s
+
+        self.grapheme_boundary(offset.min(self.len()), false)
+    }
+
+    pub fn read(&self) -> String {
+        self.chunks(0, self.len()).collect()
     }
 }
 
@@ -417,4 +576,310 @@ mod tests {
 
         assert_eq!(pt.read(), "");
     }
+
+    #[test]
+    fn undo_and_redo_insert() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+
+        pt.insert(" World", 5);
+        assert_eq!(pt.undo(), Some(5));
+        assert_eq!(pt.read(), "Hello");
+
+        assert_eq!(pt.redo(), Some(5));
+        assert_eq!(pt.read(), "Hello World");
+    }
+
+    #[test]
+    fn undo_delete() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+
+        pt.delete(1, 3);
+        assert_eq!(pt.read(), "Ho");
+
+        pt.undo();
+        assert_eq!(pt.read(), "Hello");
+    }
+
+    #[test]
+    fn undo_on_empty_history() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+
+        assert_eq!(pt.undo(), None);
+        assert_eq!(pt.redo(), None);
+        assert_eq!(pt.read(), "Hello");
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut pt = PieceTable::new();
+        pt.insert("A", 0);
+        pt.undo();
+
+        pt.insert("B", 0);
+        assert_eq!(pt.redo(), None);
+        assert_eq!(pt.read(), "B");
+    }
+
+    #[test]
+    fn undo_group_is_undone_in_one_step() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+
+        pt.begin_undo_group();
+        pt.insert(" ", 5);
+        pt.insert("W", 6);
+        pt.insert("o", 7);
+        pt.end_undo_group();
+        pt.delete(0, 1);
+
+        pt.undo();
+        assert_eq!(pt.read(), "Hello Wo");
+
+        pt.undo();
+        assert_eq!(pt.read(), "Hello");
+
+        pt.redo();
+        assert_eq!(pt.read(), "Hello Wo");
+    }
+
+    #[test]
+    fn line_count_over_multiple_pieces() {
+        let mut pt = PieceTable::init(String::from("one\ntwo"));
+        assert_eq!(pt.line_count(), 2);
+
+        pt.insert("\nthree\n", 3);
+        assert_eq!(pt.read(), "one\nthree\n\ntwo");
+        assert_eq!(pt.line_count(), 4);
+
+        pt.delete(3, 7);
+        assert_eq!(pt.read(), "one\ntwo");
+        assert_eq!(pt.line_count(), 2);
+    }
+
+    #[test]
+    fn line_start_over_multiple_pieces() {
+        let mut pt = PieceTable::init(String::from("one\ntwo\n"));
+        pt.append("three\nfour");
+
+        assert_eq!(pt.line_start(0), Some(0));
+        assert_eq!(pt.line_start(1), Some(4));
+        assert_eq!(pt.line_start(2), Some(8));
+        assert_eq!(pt.line_start(3), Some(14));
+        assert_eq!(pt.line_start(4), None);
+    }
+
+    #[test]
+    fn offset_to_line_col_and_back() {
+        let mut pt = PieceTable::init(String::from("Hello\nWorld"));
+        pt.insert("\n\n", 5);
+
+        assert_eq!(pt.offset_to_line_col(0), (0, 0));
+        assert_eq!(pt.offset_to_line_col(5), (0, 5));
+        assert_eq!(pt.offset_to_line_col(6), (1, 0));
+        assert_eq!(pt.offset_to_line_col(7), (2, 0));
+        assert_eq!(pt.offset_to_line_col(8), (3, 0));
+        assert_eq!(pt.offset_to_line_col(10), (3, 2));
+        assert_eq!(pt.offset_to_line_col(13), (3, 5));
+
+        assert_eq!(pt.line_col_to_offset(3, 2), Some(10));
+        assert_eq!(pt.line_col_to_offset(1, 0), Some(6));
+        assert_eq!(pt.line_col_to_offset(4, 0), None);
+    }
+
+    #[test]
+    fn line_col_to_offset_clamps_to_line_end() {
+        let pt = PieceTable::init(String::from("long line\nab\n"));
+
+        assert_eq!(pt.line_col_to_offset(1, 7), Some(12));
+        assert_eq!(pt.line_col_to_offset(2, 4), Some(13));
+    }
+
+    // xorshift, good enough to generate edits
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn random_edits_match_string() {
+        let base: String = (0..200).map(|i| format!("line {i}\n")).collect();
+        let mut pt = PieceTable::init(base.clone());
+        let mut expected = base;
+
+        let mut state = 0x2545f4914f6cdd1d;
+        for i in 0..2000 {
+            let len = expected.len() as u64;
+            if next_random(&mut state).is_multiple_of(3) && len > 0 {
+                let offset = next_random(&mut state) % len;
+                let length = 1 + next_random(&mut state) % (len - offset).min(20);
+                assert!(pt.delete(offset as u32, length as u32));
+                expected.replace_range(offset as usize..(offset + length) as usize, "");
+            } else {
+                let offset = next_random(&mut state) % (len + 1);
+                let content = if i % 5 == 0 { "\nab" } else { "xyz" };
+                assert!(pt.insert(content, offset as u32));
+                expected.insert_str(offset as usize, content);
+            }
+        }
+
+        assert_eq!(pt.read(), expected);
+        assert_eq!(pt.line_count(), expected.split('\n').count() as u32);
+        assert_eq!(pt.line_start(100), expected.match_indices('\n').nth(99).map(|(i, _)| i as u32 + 1));
+    }
+
+    #[test]
+    fn many_random_edits_on_large_file_stay_fast() {
+        let base = "Lorem ipsum dolor sit amet, consectetur adipiscing elit\n".repeat(80_000);
+        let mut pt = PieceTable::init(base);
+        let mut expected_len = pt.len();
+
+        let start = std::time::Instant::now();
+        let mut state = 0x9e3779b97f4a7c15;
+        for _ in 0..100_000 {
+            let offset = (next_random(&mut state) % expected_len as u64) as u32;
+            if next_random(&mut state).is_multiple_of(2) {
+                assert!(pt.delete(offset, 1));
+                expected_len -= 1;
+            } else {
+                assert!(pt.insert("a\n", offset));
+                expected_len += 2;
+            }
+            pt.offset_to_line_col(offset);
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(pt.len(), expected_len);
+        assert_eq!(pt.read().len() as u32, expected_len);
+        assert!(elapsed.as_secs() < 10, "100k edits took {elapsed:?}");
+    }
+
+    #[test]
+    fn edits_inside_a_character_are_refused() {
+        let mut pt = PieceTable::init(String::from("caf\u{e9}"));
+
+        assert!(!pt.insert("x", 4));
+        assert!(!pt.delete(3, 1));
+        assert!(!pt.delete(4, 1));
+        assert_eq!(pt.read(), "caf\u{e9}");
+
+        assert!(pt.delete(3, 2));
+        assert_eq!(pt.read(), "caf");
+    }
+
+    #[test]
+    fn grapheme_boundaries_in_accented_text() {
+        // "e" followed by a combining acute accent is one grapheme of 3 bytes
+        let pt = PieceTable::init(String::from("ae\u{301}b"));
+
+        assert_eq!(pt.next_grapheme_boundary(1), 4);
+        assert_eq!(pt.prev_grapheme_boundary(4), 1);
+        assert_eq!(pt.next_grapheme_boundary(4), 5);
+    }
+
+    #[test]
+    fn grapheme_boundaries_in_cjk_text() {
+        let mut pt = PieceTable::init(String::from("\u{65e5}\u{672c}"));
+        pt.append("\u{8a9e}");
+
+        assert_eq!(pt.next_grapheme_boundary(0), 3);
+        assert_eq!(pt.next_grapheme_boundary(3), 6);
+        assert_eq!(pt.next_grapheme_boundary(6), 9);
+        assert_eq!(pt.prev_grapheme_boundary(9), 6);
+        assert_eq!(pt.offset_to_line_col(9), (0, 9));
+    }
+
+    #[test]
+    fn grapheme_boundaries_in_emoji() {
+        // Family emoji joined with zero width joiners, spread over multiple pieces
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let mut pt = PieceTable::init(String::from("a"));
+        pt.append(&family[..4]);
+        pt.append(&family[4..]);
+        pt.append("\u{1f1e7}\u{1f1ea}b");
+
+        let family_end = 1 + family.len() as u32;
+        assert_eq!(pt.next_grapheme_boundary(1), family_end);
+        assert_eq!(pt.prev_grapheme_boundary(family_end), 1);
+
+        // Regional indicators pair up into a single flag
+        assert_eq!(pt.next_grapheme_boundary(family_end), family_end + 8);
+    }
+
+    #[test]
+    fn grapheme_boundaries_across_lines() {
+        let pt = PieceTable::init(String::from("a\r\nb\n"));
+
+        assert_eq!(pt.next_grapheme_boundary(1), 3);
+        assert_eq!(pt.prev_grapheme_boundary(3), 1);
+        assert_eq!(pt.prev_grapheme_boundary(4), 3);
+        assert_eq!(pt.next_grapheme_boundary(4), 5);
+        assert_eq!(pt.next_grapheme_boundary(5), 5);
+    }
+
+    #[test]
+    fn slice_across_pieces() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+        pt.append(" World");
+
+        assert_eq!(pt.slice(3, 8), "lo Wo");
+        assert_eq!(pt.slice(5, 5), "");
+        assert_eq!(pt.slice(6, 100), "World");
+    }
+
+    #[test]
+    fn modified_after_edit_until_saved() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+        assert!(!pt.is_modified());
+
+        pt.insert("!", 5);
+        assert!(pt.is_modified());
+
+        pt.mark_saved();
+        assert!(!pt.is_modified());
+    }
+
+    #[test]
+    fn undo_back_to_saved_state_is_not_modified() {
+        let mut pt = PieceTable::init(String::from("Hello"));
+        pt.insert("!", 5);
+        pt.mark_saved();
+
+        pt.delete(0, 1);
+        assert!(pt.is_modified());
+
+        pt.undo();
+        assert!(!pt.is_modified());
+
+        pt.undo();
+        assert!(pt.is_modified());
+
+        pt.redo();
+        assert!(!pt.is_modified());
+    }
+
+    #[test]
+    fn edits_inside_undo_group_are_modified_after_save() {
+        let mut pt = PieceTable::new();
+        pt.begin_undo_group();
+        pt.insert("a", 0);
+        pt.mark_saved();
+
+        pt.insert("b", 1);
+        assert!(pt.is_modified());
+    }
+
+    #[test]
+    fn chunks_rev_walks_pieces_backward() {
+        let mut pt = PieceTable::init(String::from("Hello world"));
+        pt.insert(",", 5);
+        pt.insert("!", 12);
+        assert_eq!(pt.read(), "Hello, world!");
+
+        let chunks: Vec<(u32, &str)> = pt.chunks_rev(0, pt.len()).collect();
+        assert_eq!(chunks, vec![(12, "!"), (6, " world"), (5, ","), (0, "Hello")]);
+        let chunks: Vec<(u32, &str)> = pt.chunks_rev(3, 8).collect();
+        assert_eq!(chunks, vec![(6, " w"), (5, ","), (3, "lo")]);
+        assert_eq!(pt.chunks_rev(4, 4).count(), 0);
+    }
 }
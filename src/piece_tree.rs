@@ -0,0 +1,291 @@
+use std::rc::Rc;
+
+use crate::piece_table::Piece;
+
+// Persistent treap ordered by document position. Nodes are shared between versions of the tree,
+// so keeping old versions around for undo only costs the nodes that were changed since.
+
+#[derive(Debug)]
+struct Node {
+    piece: Piece,
+    priority: u32,
+    // Totals for the whole subtree
+    length: u32,
+    line_breaks: u32,
+    left: Tree,
+    right: Tree,
+}
+
+type Tree = Option<Rc<Node>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct PieceTree {
+    root: Tree,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PieceLocation {
+    pub piece: Piece,
+    pub piece_start: u32,
+    // Line breaks in all the pieces before this one
+    pub line_breaks_before: u32,
+}
+
+fn length(tree: &Tree) -> u32 {
+    tree.as_ref().map_or(0, |n| n.length)
+}
+
+fn line_breaks(tree: &Tree) -> u32 {
+    tree.as_ref().map_or(0, |n| n.line_breaks)
+}
+
+fn node(piece: Piece, priority: u32, left: Tree, right: Tree) -> Tree {
+    Some(Rc::new(Node {
+        piece,
+        priority,
+        length: length(&left) + piece.length + length(&right),
+        line_breaks: line_breaks(&left) + piece.line_breaks + line_breaks(&right),
+        left,
+        right,
+    }))
+}
+
+fn leaf(piece: Piece) -> Tree {
+    node(piece, priority(&piece), None, None)
+}
+
+// Pieces never share the same add buffer range, so hashing them gives well spread priorities
+fn priority(piece: &Piece) -> u32 {
+    let mut x = ((piece.offset as u64) << 32) ^ (piece.length as u64) ^ ((piece.is_add as u64) << 63);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^= x >> 33;
+    x as u32
+}
+
+fn merge(a: Tree, b: Tree) -> Tree {
+    match (a, b) {
+        (None, b) => b,
+        (a, None) => a,
+        (Some(a), Some(b)) => {
+            if a.priority > b.priority {
+                let right = merge(a.right.clone(), Some(b));
+                node(a.piece, a.priority, a.left.clone(), right)
+            } else {
+                let left = merge(Some(a), b.left.clone());
+                node(b.piece, b.priority, left, b.right.clone())
+            }
+        }
+    }
+}
+
+/// Splits so that the left tree holds exactly `offset` bytes, cutting a piece in two when needed
+fn split<F>(tree: &Tree, offset: u32, split_piece: &F) -> (Tree, Tree)
+where
+    F: Fn(Piece, u32) -> (Piece, Piece),
+{
+    let n = match tree {
+        Some(n) => n,
+        None => return (None, None),
+    };
+
+    let left_len = length(&n.left);
+    if offset <= left_len {
+        let (l, r) = split(&n.left, offset, split_piece);
+        (l, node(n.piece, n.priority, r, n.right.clone()))
+    } else if offset >= left_len + n.piece.length {
+        let (l, r) = split(&n.right, offset - left_len - n.piece.length, split_piece);
+        (node(n.piece, n.priority, n.left.clone(), l), r)
+    } else {
+        // The halves keep the priority of the piece they replace, a fresh one could be higher than
+        // the ancestors above this node
+        let (one, two) = split_piece(n.piece, offset - left_len);
+        (
+            merge(n.left.clone(), node(one, n.priority, None, None)),
+            merge(node(two, n.priority, None, None), n.right.clone()),
+        )
+    }
+}
+
+impl PieceTree {
+    pub fn new() -> Self {
+        PieceTree { root: None }
+    }
+
+    pub fn len(&self) -> u32 {
+        length(&self.root)
+    }
+
+    pub fn line_breaks(&self) -> u32 {
+        line_breaks(&self.root)
+    }
+
+    /// `split_piece` cuts a piece at a relative offset, it is only called when `offset` falls inside a piece
+    pub fn insert<F>(&mut self, offset: u32, piece: Piece, split_piece: &F) -> bool
+    where
+        F: Fn(Piece, u32) -> (Piece, Piece),
+    {
+        if offset > self.len() {
+            return false;
+        }
+
+        let (left, right) = split(&self.root, offset, split_piece);
+        self.root = merge(merge(left, leaf(piece)), right);
+        true
+    }
+
+    pub fn delete<F>(&mut self, offset: u32, length: u32, split_piece: &F) -> bool
+    where
+        F: Fn(Piece, u32) -> (Piece, Piece),
+    {
+        if offset + length > self.len() {
+            return false;
+        }
+
+        let (left, rest) = split(&self.root, offset, split_piece);
+        let (_, right) = split(&rest, length, split_piece);
+        self.root = merge(left, right);
+        true
+    }
+
+    pub fn push(&mut self, piece: Piece) {
+        self.root = merge(self.root.take(), leaf(piece));
+    }
+
+    /// Finds the piece containing `offset`, an offset on the boundary of two pieces returns the first one
+    pub fn find_offset(&self, offset: u32) -> Option<PieceLocation> {
+        let mut offset = offset;
+        let mut piece_start = 0;
+        let mut line_breaks_before = 0;
+
+        let mut tree = &self.root;
+        while let Some(n) = tree {
+            let left_len = length(&n.left);
+            if offset <= left_len && n.left.is_some() {
+                tree = &n.left;
+            } else if offset <= left_len + n.piece.length {
+                return Some(PieceLocation {
+                    piece: n.piece,
+                    piece_start: piece_start + left_len,
+                    line_breaks_before: line_breaks_before + line_breaks(&n.left),
+                });
+            } else {
+                offset -= left_len + n.piece.length;
+                piece_start += left_len + n.piece.length;
+                line_breaks_before += line_breaks(&n.left) + n.piece.line_breaks;
+                tree = &n.right;
+            }
+        }
+
+        None
+    }
+
+    /// Finds the piece holding the line break with index `line_break`
+    pub fn find_line_break(&self, line_break: u32) -> Option<PieceLocation> {
+        let mut line_break = line_break;
+        let mut piece_start = 0;
+        let mut line_breaks_before = 0;
+
+        let mut tree = &self.root;
+        while let Some(n) = tree {
+            let left_breaks = line_breaks(&n.left);
+            if line_break < left_breaks {
+                tree = &n.left;
+            } else if line_break < left_breaks + n.piece.line_breaks {
+                return Some(PieceLocation {
+                    piece: n.piece,
+                    piece_start: piece_start + length(&n.left),
+                    line_breaks_before: line_breaks_before + left_breaks,
+                });
+            } else {
+                line_break -= left_breaks + n.piece.line_breaks;
+                piece_start += length(&n.left) + n.piece.length;
+                line_breaks_before += left_breaks + n.piece.line_breaks;
+                tree = &n.right;
+            }
+        }
+
+        None
+    }
+
+    /// Iterates from the piece holding the byte at `offset`, also returns where that piece starts
+    pub fn iter_from(&self, offset: u32) -> (PieceIter<'_>, u32) {
+        let mut iter = PieceIter { stack: Vec::new() };
+        let mut offset = offset;
+        let mut piece_start = 0;
+
+        let mut tree = &self.root;
+        while let Some(n) = tree {
+            let left_len = length(&n.left);
+            if offset < left_len {
+                iter.stack.push(n);
+                tree = &n.left;
+            } else if offset < left_len + n.piece.length {
+                iter.stack.push(n);
+                return (iter, piece_start + left_len);
+            } else {
+                offset -= left_len + n.piece.length;
+                piece_start += left_len + n.piece.length;
+                tree = &n.right;
+            }
+        }
+
+        (iter, piece_start)
+    }
+}
+
+pub struct PieceIter<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> PieceIter<'a> {
+    fn push_left(&mut self, tree: &'a Tree) {
+        let mut tree = tree;
+        while let Some(n) = tree {
+            self.stack.push(n);
+            tree = &n.left;
+        }
+    }
+}
+
+impl<'a> Iterator for PieceIter<'a> {
+    type Item = Piece;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.stack.pop()?;
+        self.push_left(&n.right);
+        Some(n.piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_heap(tree: &Tree, max: u32) -> bool {
+        match tree {
+            Some(n) => n.priority <= max && is_heap(&n.left, n.priority) && is_heap(&n.right, n.priority),
+            None => true,
+        }
+    }
+
+    #[test]
+    fn split_pieces_keep_the_heap_order() {
+        let split_piece = |p: Piece, at: u32| {
+            (
+                Piece { length: at, ..p },
+                Piece { offset: p.offset + at, length: p.length - at, ..p },
+            )
+        };
+        let mut tree = PieceTree::new();
+        tree.push(Piece { is_add: false, offset: 0, length: 1000, line_breaks: 0 });
+        for i in 0..200 {
+            let piece = Piece { is_add: true, offset: i, length: 1, line_breaks: 0 };
+            assert!(tree.insert((i * 7919) % tree.len(), piece, &split_piece));
+            assert!(is_heap(&tree.root, u32::MAX));
+        }
+        assert_eq!(tree.len(), 1200);
+    }
+}
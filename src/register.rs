@@ -0,0 +1,153 @@
+use sdl2::clipboard::ClipboardUtil;
+
+/// Text that was deleted or yanked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+    pub text: String,
+    // Whole lines were taken, they are put back as lines
+    pub linewise: bool,
+}
+
+impl Register {
+    /// Text from other applications is pasted as lines when it ends with a line break
+    fn from_clipboard(text: String) -> Self {
+        let linewise = text.ends_with('\n');
+        Register { text, linewise }
+    }
+}
+
+/// Every register that can be picked with `"`: the unnamed register `"` that is used when none is
+/// picked, `0` with the last yank, `a`-`z` (uppercase appends), the black hole `_` and the system
+/// clipboard `+` (or `*`)
+pub struct Registers {
+    unnamed: Option<Register>,
+    yank: Option<Register>,
+    named: [Option<Register>; 26],
+    // Without a clipboard, like in tests, `+` is kept here
+    clipboard_fallback: Option<Register>,
+    pub clipboard: Option<ClipboardUtil>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Registers {
+            unnamed: None,
+            yank: None,
+            named: Default::default(),
+            clipboard_fallback: None,
+            clipboard: None,
+        }
+    }
+
+    pub fn is_valid(name: char) -> bool {
+        matches!(name, '"' | '0' | '_' | '+' | '*') || name.is_ascii_alphabetic()
+    }
+
+    pub fn get(&self, name: char) -> Option<Register> {
+        match name {
+            '"' => self.unnamed.clone(),
+            '0' => self.yank.clone(),
+            'a'..='z' | 'A'..='Z' => self.named[Self::named_index(name)].clone(),
+            '+' | '*' => match &self.clipboard {
+                Some(clipboard) => clipboard.clipboard_text().ok()
+                    .filter(|text| !text.is_empty())
+                    .map(Register::from_clipboard),
+                None => self.clipboard_fallback.clone(),
+            },
+            _ => None,
+        }
+    }
+
+    /// Stores deleted or yanked text in register `name`, or only in the unnamed register when no
+    /// register was picked. Yanks are also kept in `0`, so they survive later deletes.
+    pub fn store(&mut self, name: Option<char>, register: Register, yank: bool) -> Result<(), String> {
+        let register = match name {
+            Some('_') => return Ok(()),
+            Some(name @ 'A'..='Z') => {
+                let index = Self::named_index(name);
+                let appended = match self.named[index].take() {
+                    Some(mut existing) => {
+                        // Appending lines to text makes it linewise
+                        if register.linewise && !existing.text.is_empty() && !existing.text.ends_with('\n') {
+                            existing.text.push('\n');
+                        }
+                        existing.text.push_str(&register.text);
+                        existing.linewise |= register.linewise;
+                        existing
+                    },
+                    None => register,
+                };
+                self.named[index] = Some(appended.clone());
+                appended
+            },
+            Some(name @ 'a'..='z') => {
+                self.named[Self::named_index(name)] = Some(register.clone());
+                register
+            },
+            Some('+' | '*') => {
+                match &self.clipboard {
+                    Some(clipboard) => clipboard.set_clipboard_text(&register.text)
+                        .map_err(|e| format!("Failed to copy to the clipboard: {}", e))?,
+                    None => self.clipboard_fallback = Some(register.clone()),
+                }
+                register
+            },
+            _ => {
+                if yank {
+                    self.yank = Some(register.clone());
+                }
+                register
+            },
+        };
+
+        self.unnamed = Some(register);
+        Ok(())
+    }
+
+    fn named_index(name: char) -> usize {
+        (name.to_ascii_lowercase() as u8 - b'a') as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::register::{Register, Registers};
+
+    fn register(text: &str, linewise: bool) -> Register {
+        Register { text: String::from(text), linewise }
+    }
+
+    #[test]
+    fn yanks_survive_deletes() {
+        let mut registers = Registers::new();
+        registers.store(None, register("yanked", false), true).unwrap();
+        registers.store(None, register("deleted\n", true), false).unwrap();
+
+        assert_eq!(registers.get('"'), Some(register("deleted\n", true)));
+        assert_eq!(registers.get('0'), Some(register("yanked", false)));
+    }
+
+    #[test]
+    fn named_registers_append_in_uppercase() {
+        let mut registers = Registers::new();
+        registers.store(Some('a'), register("foo", false), true).unwrap();
+        registers.store(Some('A'), register("bar", false), true).unwrap();
+        assert_eq!(registers.get('a'), Some(register("foobar", false)));
+
+        registers.store(Some('A'), register("line\n", true), false).unwrap();
+        assert_eq!(registers.get('A'), Some(register("foobar\nline\n", true)));
+        // A yank into a named register leaves `0` alone
+        assert_eq!(registers.get('0'), None);
+        assert_eq!(registers.get('"'), Some(register("foobar\nline\n", true)));
+    }
+
+    #[test]
+    fn black_hole_keeps_nothing() {
+        let mut registers = Registers::new();
+        registers.store(None, register("kept", false), false).unwrap();
+        registers.store(Some('_'), register("gone", false), false).unwrap();
+
+        assert_eq!(registers.get('_'), None);
+        assert_eq!(registers.get('"'), Some(register("kept", false)));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::piece_table::PieceTable;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+impl SearchDirection {
+    pub fn reverse(&self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
+    }
+
+    /// The character that starts a search in this direction
+    pub fn prefix(&self) -> char {
+        match self {
+            SearchDirection::Forward => '/',
+            SearchDirection::Backward => '?',
+        }
+    }
+}
+
+/// Calls `f` with the start of every match of `query` that starts in `start..end`, until it returns
+/// false. Matches don't overlap. Each chunk is searched where it lies in the buffers, only the few
+/// bytes around a chunk boundary are copied to find the matches that cross it.
+fn for_each_match(pt: &PieceTable, query: &str, start: u32, end: u32, mut f: impl FnMut(u32) -> bool) {
+    if query.is_empty() || start >= end {
+        return;
+    }
+
+    // Matches starting just before `end` still need the text after it
+    let mut search_end = (end + query.len() as u32 - 1).min(pt.len());
+    while search_end < pt.len() && !pt.is_char_boundary(search_end) {
+        search_end += 1;
+    }
+    // The end of the previous chunks, too short to hold a match but where one can start
+    let mut tail = String::new();
+    let mut joined = String::new();
+    let mut chunk_start = start;
+    let mut next = start;
+
+    for chunk in pt.chunks(start, search_end) {
+        if !tail.is_empty() {
+            let tail_start = chunk_start - tail.len() as u32;
+            joined.clear();
+            joined.push_str(&tail);
+            joined.push_str(&chunk[..ceil_char_boundary(chunk, query.len() - 1)]);
+
+            let skip = next.saturating_sub(tail_start) as usize;
+            for (i, _) in joined[skip..].match_indices(query) {
+                // The rest lies inside the chunk and is found below
+                if skip + i >= tail.len() {
+                    break;
+                }
+                let offset = tail_start + (skip + i) as u32;
+                if offset >= end || !f(offset) {
+                    return;
+                }
+                next = offset + query.len() as u32;
+            }
+        }
+
+        // Matches found across the previous boundary are not searched again
+        let skip = (next.saturating_sub(chunk_start) as usize).min(chunk.len());
+        for (i, _) in chunk[skip..].match_indices(query) {
+            let offset = chunk_start + (skip + i) as u32;
+            if offset >= end || !f(offset) {
+                return;
+            }
+            next = offset + query.len() as u32;
+        }
+
+        tail.push_str(&chunk[floor_char_boundary(chunk, chunk.len().saturating_sub(query.len() - 1))..]);
+        tail.drain(..floor_char_boundary(&tail, tail.len().saturating_sub(query.len() - 1)));
+        chunk_start += chunk.len() as u32;
+    }
+}
+
+/// The last match that starts in `start..end`, searched from `end` back one chunk at a time.
+/// Unlike `for_each_match` matches may overlap, as the closest one to `end` is wanted.
+fn last_match(pt: &PieceTable, query: &str, start: u32, end: u32) -> Option<u32> {
+    if query.is_empty() || start >= end {
+        return None;
+    }
+
+    // The start of the text after the current chunk, a match starting in the chunk can reach into it
+    let mut head_end = (end + query.len() as u32 - 1).min(pt.len());
+    while head_end < pt.len() && !pt.is_char_boundary(head_end) {
+        head_end += 1;
+    }
+    let mut head = pt.slice(end, head_end);
+    let mut joined = String::new();
+
+    for (chunk_start, chunk) in pt.chunks_rev(start, end) {
+        // Matches that cross into the text after the chunk are closer to `end` than the ones inside it
+        let tail = floor_char_boundary(chunk, chunk.len().saturating_sub(query.len() - 1));
+        joined.clear();
+        joined.push_str(&chunk[tail..]);
+        joined.push_str(&head);
+        let crossing = (0..chunk.len() - tail).rev()
+            .find(|i| joined.is_char_boundary(*i) && joined[*i..].starts_with(query));
+        if let Some(i) = crossing {
+            return Some(chunk_start + (tail + i) as u32);
+        }
+
+        if let Some(i) = chunk.rfind(query) {
+            return Some(chunk_start + i as u32);
+        }
+
+        head.insert_str(0, &chunk[..ceil_char_boundary(chunk, query.len() - 1)]);
+        head.truncate(ceil_char_boundary(&head, query.len() - 1));
+    }
+
+    None
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+/// Every match that starts in `start..end`
+pub fn matches(pt: &PieceTable, query: &str, start: u32, end: u32) -> Vec<u32> {
+    let mut matches = Vec::new();
+    for_each_match(pt, query, start, end, |offset| {
+        matches.push(offset);
+        true
+    });
+    matches
+}
+
+fn first_match(pt: &PieceTable, query: &str, start: u32, end: u32) -> Option<u32> {
+    let mut found = None;
+    for_each_match(pt, query, start, end, |offset| {
+        found = Some(offset);
+        false
+    });
+    found
+}
+
+/// The closest match after (or before) `from`, continuing at the other end of the buffer when
+/// there is none. The flag tells whether the search wrapped around.
+pub fn find(pt: &PieceTable, query: &str, from: u32, direction: SearchDirection) -> Option<(u32, bool)> {
+    let len = pt.len();
+    match direction {
+        SearchDirection::Forward => {
+            let after = pt.next_grapheme_boundary(from);
+            first_match(pt, query, after, len).map(|offset| (offset, false))
+                .or_else(|| first_match(pt, query, 0, after.min(len)).map(|offset| (offset, true)))
+        },
+        SearchDirection::Backward => {
+            last_match(pt, query, 0, from).map(|offset| (offset, false))
+                .or_else(|| last_match(pt, query, from, len).map(|offset| (offset, true)))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::piece_table::PieceTable;
+    use crate::search::{find, matches, SearchDirection};
+
+    #[test]
+    fn matches_across_pieces() {
+        let mut pt = PieceTable::init(String::from("abc ab"));
+        pt.insert("c", 6);
+        pt.insert("\u{e9}ab", 0);
+        assert_eq!(pt.read(), "\u{e9}ababc abc");
+
+        assert_eq!(matches(&pt, "abc", 0, pt.len()), vec![4, 8]);
+        assert_eq!(matches(&pt, "\u{e9}a", 0, pt.len()), vec![0]);
+        // Only matches starting in the range count, even when they end after it
+        assert_eq!(matches(&pt, "abc", 5, 9), vec![8]);
+        assert_eq!(matches(&pt, "aa", 0, pt.len()), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn matches_across_short_pieces() {
+        // The middle piece is shorter than the query, so the match spans three pieces
+        let mut pt = PieceTable::init(String::from("xxabyzabcdx"));
+        pt.insert("c", 4);
+        pt.insert("d", 5);
+        assert_eq!(pt.read(), "xxabcdyzabcdx");
+
+        assert_eq!(matches(&pt, "abcd", 0, pt.len()), vec![2, 8]);
+        assert_eq!(find(&pt, "abcd", 8, SearchDirection::Backward), Some((2, false)));
+        assert_eq!(find(&pt, "abcd", 2, SearchDirection::Backward), Some((8, true)));
+        assert_eq!(find(&pt, "bcdy", 12, SearchDirection::Backward), Some((3, false)));
+    }
+
+    #[test]
+    fn matches_do_not_overlap() {
+        let pt = PieceTable::init(String::from("aaaaa"));
+        assert_eq!(matches(&pt, "aa", 0, pt.len()), vec![0, 2]);
+
+        let mut pt = PieceTable::init(String::from("aa"));
+        pt.insert("aa", 2);
+        assert_eq!(matches(&pt, "aa", 0, pt.len()), vec![0, 2]);
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let pt = PieceTable::init(String::from("foo bar foo"));
+
+        assert_eq!(find(&pt, "foo", 0, SearchDirection::Forward), Some((8, false)));
+        assert_eq!(find(&pt, "foo", 8, SearchDirection::Forward), Some((0, true)));
+        assert_eq!(find(&pt, "foo", 8, SearchDirection::Backward), Some((0, false)));
+        assert_eq!(find(&pt, "foo", 0, SearchDirection::Backward), Some((8, true)));
+        assert_eq!(find(&pt, "baz", 0, SearchDirection::Forward), None);
+
+        // The text after `from` that a match can reach into ends inside a multibyte character
+        let pt = PieceTable::init(String::from("ab\u{e9}ab"));
+        assert_eq!(find(&pt, "ab", 2, SearchDirection::Backward), Some((0, false)));
+        assert_eq!(matches(&pt, "\u{e9}a", 0, 3), vec![2]);
+    }
+}
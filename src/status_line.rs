@@ -0,0 +1,66 @@
+use crate::{cursor::Cursor, editor::Editor};
+
+/// What is shown about the current buffer at the bottom of the window, `left` starts at the left
+/// edge and `right` ends at the right edge
+#[derive(Debug, PartialEq)]
+pub struct StatusLine {
+    pub left: String,
+    pub right: String,
+}
+
+impl StatusLine {
+    pub fn new(editor: &Editor) -> Self {
+        let buffer = editor.buffers.current();
+        let modified = if buffer.is_modified() { " [+]" } else { "" };
+        let left = format!(" {} | {}{}", editor.mode.name().to_uppercase(), buffer.display_name(), modified);
+
+        let (line, _) = buffer.pt.offset_to_line_col(editor.cursor.index);
+        let column = Cursor::column(&buffer.pt, editor.cursor.index);
+        // Files are only ever read as UTF-8
+        let right = format!(
+            "utf-8 | {} | {}:{} | {} lines ",
+            buffer.line_ending(),
+            line + 1,
+            column + 1,
+            buffer.pt.line_count(),
+        );
+
+        StatusLine { left, right }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::Buffer;
+    use crate::editor::{Editor, Mode};
+    use crate::piece_table::PieceTable;
+    use crate::status_line::StatusLine;
+
+    #[test]
+    fn shows_mode_path_and_position() {
+        let mut editor = Editor::new((10, 20));
+        assert_eq!(StatusLine::new(&editor), StatusLine {
+            left: String::from(" NORMAL | [No Name]"),
+            right: String::from("utf-8 | lf | 1:1 | 1 lines "),
+        });
+
+        editor.buffers.open(Buffer::from("notes.txt", String::from("one\r\n\u{e9}t\u{e9}\r\n")), &mut editor.cursor);
+        editor.cursor.index = 10;
+        editor.buffers.current_mut().pt.insert("!", 10);
+        editor.mode = Mode::Insert;
+        assert_eq!(StatusLine::new(&editor), StatusLine {
+            left: String::from(" INSERT | notes.txt [+]"),
+            right: String::from("utf-8 | crlf | 2:4 | 3 lines "),
+        });
+    }
+
+    #[test]
+    fn line_ending_after_multibyte_character() {
+        let mut editor = Editor::new((10, 20));
+        editor.buffers.open(Buffer::from("cafe.txt", String::from("caf\u{e9}\nnext")), &mut editor.cursor);
+        assert_eq!(StatusLine::new(&editor).right, "utf-8 | lf | 1:1 | 2 lines ");
+
+        editor.buffers.current_mut().pt = PieceTable::init(String::from("caf\u{e9}\r\nnext"));
+        assert_eq!(StatusLine::new(&editor).right, "utf-8 | crlf | 1:1 | 2 lines ");
+    }
+}
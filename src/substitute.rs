@@ -0,0 +1,205 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::piece_table::PieceTable;
+
+/// A parsed `:s/pattern/replacement/flags` command
+#[derive(Debug)]
+pub struct Substitution {
+    pub pattern: Regex,
+    // In the syntax of `regex`, converted from the vim style `\1` and `&`
+    replacement: String,
+    // Every match of a line instead of only the first
+    pub global: bool,
+    // Asks before replacing each match
+    pub confirm: bool,
+    // `:%s` works on the whole buffer, `:s` on the line of the cursor
+    pub whole_buffer: bool,
+}
+
+/// A match with the text it is replaced by
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    pub start: u32,
+    pub end: u32,
+    pub text: String,
+}
+
+/// Splits off the text up to the next unescaped `delimiter`, an escaped delimiter stands for itself
+fn split_part(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (part, Some(&text[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                },
+                None => part.push('\\'),
+            }
+            continue;
+        }
+        part.push(c);
+    }
+
+    (part, None)
+}
+
+/// `\1` to `\9` and `&` stand for the groups and the whole match, `\n` and `\t` for a line break
+/// and a tab
+fn convert_replacement(replacement: &str) -> String {
+    let mut converted = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                Some('n') => converted.push('\n'),
+                Some('t') => converted.push('\t'),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+impl Substitution {
+    /// `None` when `command` is not a substitute command at all
+    pub fn parse(command: &str) -> Option<Result<Self, String>> {
+        let (whole_buffer, rest) = match command.strip_prefix('%') {
+            Some(rest) => (true, rest),
+            None => (false, command),
+        };
+        let rest = rest.strip_prefix('s')?;
+
+        let delimiter = rest.chars().next()?;
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+            return None;
+        }
+        Some(Self::parse_parts(&rest[delimiter.len_utf8()..], delimiter, whole_buffer))
+    }
+
+    fn parse_parts(text: &str, delimiter: char, whole_buffer: bool) -> Result<Self, String> {
+        let (pattern, rest) = split_part(text, delimiter);
+        let (replacement, flags) = match rest {
+            Some(rest) => split_part(rest, delimiter),
+            None => (String::new(), None),
+        };
+
+        let (mut global, mut confirm, mut case_insensitive) = (false, false, false);
+        for flag in flags.unwrap_or("").chars() {
+            match flag {
+                'g' => global = true,
+                'c' => confirm = true,
+                'i' => case_insensitive = true,
+                'I' => case_insensitive = false,
+                _ => return Err(format!("Unknown substitute flag: {}", flag)),
+            }
+        }
+
+        if pattern.is_empty() {
+            return Err(String::from("Empty pattern"));
+        }
+        let pattern = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|e| format!("Invalid pattern: {}", e))?;
+
+        Ok(Substitution {
+            pattern,
+            replacement: convert_replacement(&replacement),
+            global,
+            confirm,
+            whole_buffer,
+        })
+    }
+
+    /// Every replacement on the lines `first..=last`, front to back. Patterns are matched a line at a
+    /// time, so they never span line breaks.
+    pub fn replacements(&self, pt: &PieceTable, first: u32, last: u32) -> Vec<Replacement> {
+        let mut replacements = Vec::new();
+        for line in first..=last.min(pt.line_count().saturating_sub(1)) {
+            let start = pt.line_start(line).unwrap_or(0);
+            let text = pt.slice(start, start + pt.line_len(line).unwrap_or(0));
+
+            for captures in self.pattern.captures_iter(&text) {
+                let whole = captures.get(0).unwrap();
+                let mut replaced = String::new();
+                captures.expand(&self.replacement, &mut replaced);
+                replacements.push(Replacement {
+                    start: start + whole.start() as u32,
+                    end: start + whole.end() as u32,
+                    text: replaced,
+                });
+
+                if !self.global {
+                    break;
+                }
+            }
+        }
+        replacements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::piece_table::PieceTable;
+    use crate::substitute::{Replacement, Substitution};
+
+    fn parse(command: &str) -> Substitution {
+        Substitution::parse(command).unwrap().unwrap()
+    }
+
+    fn texts(substitution: &Substitution, text: &str) -> Vec<String> {
+        let pt = PieceTable::init(String::from(text));
+        substitution.replacements(&pt, 0, pt.line_count()).into_iter().map(|r| r.text).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert!(Substitution::parse("set wrap").is_none());
+        assert!(Substitution::parse("sa").is_none());
+
+        let substitution = parse("%s#a/b#c#gc");
+        assert_eq!(substitution.pattern.as_str(), "a/b");
+        assert!(substitution.whole_buffer && substitution.global && substitution.confirm);
+
+        // Escaped delimiters and a missing end
+        let substitution = parse("s/a\\/b/c\\/d");
+        assert_eq!(substitution.pattern.as_str(), "a/b");
+        assert_eq!(texts(&substitution, "a/b"), vec!["c/d"]);
+        assert!(!substitution.whole_buffer && !substitution.global);
+
+        assert_eq!(Substitution::parse("s/a/b/x").unwrap().unwrap_err(), "Unknown substitute flag: x");
+        assert!(Substitution::parse("s/(/b/").unwrap().unwrap_err().starts_with("Invalid pattern"));
+    }
+
+    #[test]
+    fn replacements_with_groups() {
+        let substitution = parse("s/(\\w+)=(\\w+)/\\2=\\1 & $1/g");
+        assert_eq!(texts(&substitution, "a=b c=d"), vec!["b=a a=b $1", "d=c c=d $1"]);
+    }
+
+    #[test]
+    fn first_match_per_line_without_global() {
+        let pt = PieceTable::init(String::from("aXa\nxa\nb"));
+        let substitution = parse("s/a/-/i");
+
+        assert_eq!(substitution.replacements(&pt, 0, 2), vec![
+            Replacement { start: 0, end: 1, text: String::from("-") },
+            Replacement { start: 5, end: 6, text: String::from("-") },
+        ]);
+
+        let substitution = parse("s/x/-/gi");
+        assert_eq!(texts(&substitution, "aXa\nxa\nb"), vec!["-", "-"]);
+    }
+}
@@ -0,0 +1,31 @@
+use std::{fs, ops::Deref, path::{Path, PathBuf}};
+
+/// An empty directory for a test, removed again when dropped so a failing test doesn't leave it
+/// behind
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// `name` has to be unique among the tests, they run at the same time
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("awildtxt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
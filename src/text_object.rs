@@ -0,0 +1,361 @@
+use crate::{motion::{self, CharClass}, piece_table::PieceTable};
+
+/// Text around the cursor that operators can work on, like `iw` or `a(`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextObject {
+    Word,
+    // Anything but whitespace
+    BigWord,
+    Quote(char),
+    Bracket(char, char),
+    Paragraph,
+}
+
+/// Consecutive text of the same kind, words and whitespace or paragraphs and blank lines
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    start: u32,
+    end: u32,
+    blank: bool,
+}
+
+impl TextObject {
+    /// The object for the key typed after `i` or `a`
+    pub fn from_char(c: char) -> Option<TextObject> {
+        match c {
+            'w' => Some(TextObject::Word),
+            'W' => Some(TextObject::BigWord),
+            '"' | '\'' | '`' => Some(TextObject::Quote(c)),
+            '(' | ')' | 'b' => Some(TextObject::Bracket('(', ')')),
+            '[' | ']' => Some(TextObject::Bracket('[', ']')),
+            '{' | '}' | 'B' => Some(TextObject::Bracket('{', '}')),
+            '<' | '>' => Some(TextObject::Bracket('<', '>')),
+            'p' => Some(TextObject::Paragraph),
+            _ => None,
+        }
+    }
+
+    pub fn is_linewise(&self) -> bool {
+        *self == TextObject::Paragraph
+    }
+
+    /// Byte range of the object at `index`, `around` includes the surrounding whitespace, quotes or
+    /// brackets. A count selects more words or paragraphs, or brackets further out.
+    pub fn range(&self, pt: &PieceTable, index: u32, count: Option<u32>, around: bool) -> Option<(u32, u32)> {
+        let count = count.unwrap_or(1).max(1);
+        match *self {
+            TextObject::Word | TextObject::BigWord => {
+                let runs = word_runs(pt, index, *self == TextObject::BigWord);
+                let (line, _) = pt.offset_to_line_col(index);
+                let line_end = pt.line_start(line)? + pt.line_len(line)?;
+                // At the end of the line the cursor is on the last word
+                let position = index.min(line_end.saturating_sub(1));
+                let current = runs.iter().position(|r| r.start <= position && position < r.end)?;
+                Some(select_runs(&runs, current, count, around))
+            },
+            TextObject::Paragraph => {
+                let (line, _) = pt.offset_to_line_col(index);
+                let (runs, current) = paragraph_runs(pt, line, count);
+                let (first, last) = select_runs(&runs, current, count, around);
+                Some((pt.line_start(first)?, pt.line_start(last).unwrap_or(pt.len())))
+            },
+            TextObject::Quote(quote) => quote_range(pt, index, quote, around),
+            TextObject::Bracket(open, close) => bracket_range(pt, index, open, close, count, around),
+        }
+    }
+}
+
+/// `count` runs starting at `current`. Around pairs every run with the one after it when one of
+/// the two is blank, without blank run at the end the one before the first is taken instead.
+fn select_runs(runs: &[Run], current: usize, count: u32, around: bool) -> (u32, u32) {
+    let mut last = current;
+    for n in 0..count {
+        if n > 0 {
+            if last + 1 >= runs.len() {
+                break;
+            }
+            last += 1;
+        }
+
+        if around && runs.get(last + 1).is_some_and(|next| next.blank || runs[last].blank) {
+            last += 1;
+        }
+    }
+
+    let mut start = runs[current].start;
+    if around && !runs[last].blank && !runs[current].blank && current > 0 && runs[current - 1].blank {
+        start = runs[current - 1].start;
+    }
+
+    (start, runs[last].end)
+}
+
+fn word_runs(pt: &PieceTable, index: u32, big: bool) -> Vec<Run> {
+    let (line, _) = pt.offset_to_line_col(index);
+    let start = pt.line_start(line).unwrap_or(0);
+    let text = pt.slice(start, start + pt.line_len(line).unwrap_or(0));
+
+    let class = |c: char| match motion::class(c) {
+        CharClass::Punctuation if big => CharClass::Word,
+        class => class,
+    };
+
+    let mut runs: Vec<(Run, CharClass)> = Vec::new();
+    for (i, c) in text.char_indices() {
+        let offset = start + i as u32;
+        let end = offset + c.len_utf8() as u32;
+        match runs.last_mut() {
+            Some((run, run_class)) if *run_class == class(c) => run.end = end,
+            _ => runs.push((Run { start: offset, end, blank: class(c) == CharClass::Space }, class(c))),
+        }
+    }
+
+    runs.into_iter().map(|(run, _)| run).collect()
+}
+
+/// Whether `line` holds nothing but whitespace
+fn is_blank_line(pt: &PieceTable, line: u32) -> bool {
+    let start = pt.line_start(line).unwrap_or(0);
+    pt.slice(start, start + pt.line_len(line).unwrap_or(0)).trim().is_empty()
+}
+
+/// The blank or non-blank lines around `line`, scanned outward until the first line of the other kind.
+/// Runs of lines hold line numbers in `start` and `end`.
+fn paragraph_run(pt: &PieceTable, line: u32) -> Run {
+    let blank = is_blank_line(pt, line);
+    let mut start = line;
+    while start > 0 && is_blank_line(pt, start - 1) == blank {
+        start -= 1;
+    }
+
+    let mut end = line + 1;
+    while end < pt.line_count() && is_blank_line(pt, end) == blank {
+        end += 1;
+    }
+
+    Run { start, end, blank }
+}
+
+/// Only the runs `select_runs` can look at: the one before the run holding `line` and, as around
+/// may take two runs per count, up to twice `count` runs after it. Returns the position of the run
+/// holding `line` too.
+fn paragraph_runs(pt: &PieceTable, line: u32, count: u32) -> (Vec<Run>, usize) {
+    let mut runs = Vec::new();
+    let run = paragraph_run(pt, line);
+    if run.start > 0 {
+        runs.push(paragraph_run(pt, run.start - 1));
+    }
+
+    let current = runs.len();
+    runs.push(run);
+    let mut after = 0;
+    while after < count.saturating_mul(2) && runs[runs.len() - 1].end < pt.line_count() {
+        runs.push(paragraph_run(pt, runs[runs.len() - 1].end));
+        after += 1;
+    }
+
+    (runs, current)
+}
+
+/// Quotes only pair up within a line, counted from its start so the cursor can be on either quote
+fn quote_range(pt: &PieceTable, index: u32, quote: char, around: bool) -> Option<(u32, u32)> {
+    let (line, _) = pt.offset_to_line_col(index);
+    let line_start = pt.line_start(line)?;
+    let text = pt.slice(line_start, line_start + pt.line_len(line)?);
+
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if c == quote && !escaped {
+            quotes.push(line_start + i as u32);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    let (open, close) = quotes.chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|(_, close)| *close >= index)?;
+
+    if !around {
+        return Some((open + 1, close));
+    }
+
+    // Like `aw`, take the whitespace after the closing quote or else the whitespace before the opening one
+    let after = &text[(close + 1 - line_start) as usize..];
+    let trailing = after.len() - after.trim_start().len();
+    if trailing > 0 {
+        return Some((open, close + 1 + trailing as u32));
+    }
+    let before = &text[..(open - line_start) as usize];
+    let leading = before.len() - before.trim_end().len();
+    Some((open - leading as u32, close + 1))
+}
+
+/// Characters before `end`, last first
+fn chars_before(pt: &PieceTable, end: u32) -> impl Iterator<Item = (u32, char)> + '_ {
+    let (last_line, _) = pt.offset_to_line_col(end);
+    (0..=last_line).rev().flat_map(move |line| {
+        let start = pt.line_start(line).unwrap_or(0);
+        let line_end = pt.line_start(line + 1).unwrap_or(pt.len()).min(end);
+        let chars: Vec<(u32, char)> = pt.slice(start, line_end).char_indices()
+            .map(|(i, c)| (start + i as u32, c))
+            .collect();
+        chars.into_iter().rev()
+    })
+}
+
+/// Characters from `start` to the end of the buffer
+fn chars_from(pt: &PieceTable, start: u32) -> impl Iterator<Item = (u32, char)> + '_ {
+    let mut offset = start;
+    pt.chunks(start, pt.len()).flat_map(move |chunk| {
+        let base = offset;
+        offset += chunk.len() as u32;
+        chunk.char_indices().map(move |(i, c)| (base + i as u32, c))
+    })
+}
+
+fn bracket_range(pt: &PieceTable, index: u32, open: char, close: char, count: u32, around: bool) -> Option<(u32, u32)> {
+    // A cursor on a bracket belongs to the pair that bracket is part of
+    let search_end = match motion::char_at(pt, index) {
+        Some((c, _)) if c == close => index,
+        Some((_, next)) => next,
+        None => index,
+    };
+
+    let mut depth = 0;
+    let mut remaining = count;
+    let mut open_index = None;
+    for (i, c) in chars_before(pt, search_end) {
+        if c == close {
+            depth += 1;
+        } else if c == open {
+            if depth > 0 {
+                depth -= 1;
+                continue;
+            }
+            remaining -= 1;
+            if remaining == 0 {
+                open_index = Some(i);
+                break;
+            }
+        }
+    }
+    let open_index = open_index?;
+
+    let mut depth = 0;
+    let close_index = chars_from(pt, open_index + 1).find(|(_, c)| {
+        if *c == open {
+            depth += 1;
+        } else if *c == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?.0;
+
+    if around {
+        return Some((open_index, close_index + 1));
+    }
+
+    // A block spread over lines keeps the line breaks after the opening and before the closing bracket
+    let mut start = open_index + 1;
+    let mut end = close_index;
+    if pt.slice(start, end).starts_with('\n') {
+        start += 1;
+    }
+    let (close_line, _) = pt.offset_to_line_col(close_index);
+    let close_line_start = pt.line_start(close_line)?;
+    if close_line_start > start && pt.slice(close_line_start, close_index).trim().is_empty() {
+        end = close_line_start - 1;
+    }
+
+    Some((start, end.max(start)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::piece_table::PieceTable;
+    use crate::text_object::TextObject;
+
+    fn select(text: &str, object: char, index: u32, count: Option<u32>, around: bool) -> Option<String> {
+        let pt = PieceTable::init(String::from(text));
+        let (start, end) = TextObject::from_char(object)?.range(&pt, index, count, around)?;
+        Some(pt.slice(start, end))
+    }
+
+    #[test]
+    fn inner_and_around_word() {
+        let text = "foo bar.baz  qux";
+        assert_eq!(select(text, 'w', 5, None, false).as_deref(), Some("bar"));
+        assert_eq!(select(text, 'w', 5, None, true).as_deref(), Some(" bar"));
+        assert_eq!(select(text, 'w', 0, None, true).as_deref(), Some("foo "));
+        assert_eq!(select(text, 'w', 10, None, true).as_deref(), Some("baz  "));
+        // Whitespace under the cursor is a word of its own
+        assert_eq!(select(text, 'w', 12, None, false).as_deref(), Some("  "));
+        assert_eq!(select(text, 'w', 12, None, true).as_deref(), Some("  qux"));
+        // Without whitespace after the word the whitespace before it is taken
+        assert_eq!(select(text, 'w', 14, None, true).as_deref(), Some("  qux"));
+        assert_eq!(select(text, 'w', 0, Some(3), false).as_deref(), Some("foo bar"));
+        assert_eq!(select(text, 'W', 5, None, false).as_deref(), Some("bar.baz"));
+    }
+
+    #[test]
+    fn word_at_line_end() {
+        assert_eq!(select("foo\nbar", 'w', 3, None, false).as_deref(), Some("foo"));
+        assert_eq!(select("\nbar", 'w', 0, None, false), None);
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let text = r#"say "hi \"you\"" and 'x' "#;
+        assert_eq!(select(text, '"', 6, None, false).as_deref(), Some(r#"hi \"you\""#));
+        assert_eq!(select(text, '"', 4, None, true).as_deref(), Some(r#""hi \"you\"" "#));
+        // Before the first quote the next string is used
+        assert_eq!(select(text, '"', 0, None, false).as_deref(), Some(r#"hi \"you\""#));
+        assert_eq!(select(text, '\'', 23, None, true).as_deref(), Some("'x' "));
+        assert_eq!(select("a \"b\"", '"', 4, None, true).as_deref(), Some(" \"b\""));
+        assert_eq!(select("\"a\"\n\"b\"", '"', 4, None, false).as_deref(), Some("b"));
+        assert_eq!(select("no quotes", '"', 0, None, false), None);
+    }
+
+    #[test]
+    fn nested_brackets() {
+        let text = "f(a, (b + c), d)";
+        assert_eq!(select(text, '(', 7, None, false).as_deref(), Some("b + c"));
+        assert_eq!(select(text, '(', 7, Some(2), false).as_deref(), Some("a, (b + c), d"));
+        assert_eq!(select(text, 'b', 3, None, true).as_deref(), Some("(a, (b + c), d)"));
+        // On a bracket itself
+        assert_eq!(select(text, '(', 5, None, false).as_deref(), Some("b + c"));
+        assert_eq!(select(text, ')', 11, None, false).as_deref(), Some("b + c"));
+        assert_eq!(select(text, ')', 15, None, false).as_deref(), Some("a, (b + c), d"));
+        assert_eq!(select(text, '[', 7, None, false), None);
+        assert_eq!(select("()", '(', 0, None, false).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn brackets_over_lines() {
+        let text = "fn x() {\n    a;\n    { b; }\n}";
+        assert_eq!(select(text, '{', 12, None, false).as_deref(), Some("    a;\n    { b; }"));
+        assert_eq!(select(text, '{', 23, None, false).as_deref(), Some(" b; "));
+        assert_eq!(select(text, '{', 23, Some(2), true).as_deref(), Some("{\n    a;\n    { b; }\n}"));
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\n\nc\nd";
+        assert_eq!(select(text, 'p', 2, None, false).as_deref(), Some("a\nb\n"));
+        assert_eq!(select(text, 'p', 0, None, true).as_deref(), Some("a\nb\n\n\n"));
+        assert_eq!(select(text, 'p', 4, None, false).as_deref(), Some("\n\n"));
+        // The last paragraph takes the blank lines before it
+        assert_eq!(select(text, 'p', 7, None, true).as_deref(), Some("\n\nc\nd"));
+        assert_eq!(select(text, 'p', 0, Some(2), false).as_deref(), Some("a\nb\n\n\n"));
+        assert_eq!(select(text, 'p', 0, Some(u32::MAX), true).as_deref(), Some(text));
+
+        let text = "a\n\nb\nc\n\n\nd\n\ne";
+        assert_eq!(select(text, 'p', 5, None, false).as_deref(), Some("b\nc\n"));
+        assert_eq!(select(text, 'p', 5, None, true).as_deref(), Some("b\nc\n\n\n"));
+        assert_eq!(select(text, 'p', 5, Some(2), true).as_deref(), Some("b\nc\n\n\nd\n\n"));
+    }
+}
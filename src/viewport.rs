@@ -0,0 +1,448 @@
+use crate::{cursor::Cursor, piece_table::PieceTable};
+
+// Lines kept visible above and below the cursor when scrolling
+const DEFAULT_SCROLL_MARGIN: u32 = 3;
+// Columns kept visible left and right of the cursor when scrolling horizontally
+const HORIZONTAL_SCROLL_MARGIN: u32 = 5;
+
+/// The part of a buffer that is visible in the window, `height` and `width` are in cells
+#[derive(Clone, Debug)]
+pub struct Viewport {
+    pub top_line: u32,
+    pub left_column: u32,
+    pub scroll_margin: u32,
+    // Long lines continue on the next row instead of scrolling horizontally
+    pub wrap: bool,
+    // Line numbers are shown left of the text
+    pub number: bool,
+    // Line numbers count from the cursor line, which keeps its own number when `number` is on too
+    pub relative_number: bool,
+    // Columns taken up by the line numbers, `width` is what is left for the text
+    pub gutter: u32,
+    pub height: u32,
+    pub width: u32,
+}
+
+/// A single row on screen, with wrapping a line can take up multiple rows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenRow {
+    pub line: u32,
+    pub start: u32,
+    pub end: u32,
+    pub first_column: u32,
+}
+
+impl Viewport {
+    pub fn new() -> Self {
+        Viewport {
+            top_line: 0,
+            left_column: 0,
+            scroll_margin: DEFAULT_SCROLL_MARGIN,
+            wrap: false,
+            number: true,
+            relative_number: false,
+            gutter: 0,
+            height: 1,
+            width: 1,
+        }
+    }
+
+    /// `width` includes the gutter
+    pub fn resize(&mut self, height: u32, width: u32) {
+        self.height = height.max(1);
+        self.width = width.saturating_sub(self.gutter).max(1);
+    }
+
+    /// Makes room for the line numbers of `line_count` lines, at least three digits and a space
+    pub fn update_gutter(&mut self, line_count: u32) {
+        self.gutter = if self.number || self.relative_number {
+            (line_count.max(1).ilog10() + 1).max(3) + 1
+        } else {
+            0
+        };
+    }
+
+    /// The line number shown in the gutter next to `line`, right aligned
+    pub fn line_number(&self, line: u32, cursor_line: u32) -> String {
+        let number = if self.relative_number && !(self.number && line == cursor_line) {
+            line.abs_diff(cursor_line)
+        } else {
+            line + 1
+        };
+        format!("{:>width$} ", number, width = self.gutter.saturating_sub(1) as usize)
+    }
+
+    /// The margin can't be more than half the screen or the cursor could never be inside it
+    fn margin(&self) -> u32 {
+        self.scroll_margin.min(self.height.saturating_sub(1) / 2)
+    }
+
+    fn horizontal_margin(&self) -> u32 {
+        HORIZONTAL_SCROLL_MARGIN.min(self.width.saturating_sub(1) / 2)
+    }
+
+    /// Scrolls as little as possible to have `line` outside of the scroll margin
+    pub fn scroll_to_line(&mut self, line: u32, line_count: u32) {
+        let margin = self.margin();
+
+        if line < self.top_line + margin {
+            self.top_line = line.saturating_sub(margin);
+        } else if line + margin >= self.top_line + self.height {
+            self.top_line = line + margin + 1 - self.height;
+        }
+
+        self.clamp(line_count);
+    }
+
+    /// Scrolls both ways to keep the cursor on screen
+    pub fn scroll_to_cursor(&mut self, pt: &PieceTable, index: u32) {
+        let (line, _) = pt.offset_to_line_col(index);
+        self.scroll_to_line(line, pt.line_count());
+
+        if self.wrap {
+            self.left_column = 0;
+
+            // Wrapped lines above the cursor can push it off the bottom of the screen
+            while self.top_line < line && self.screen_position(pt, index).is_none() {
+                self.top_line += 1;
+            }
+            return;
+        }
+
+        let column = Cursor::column(pt, index);
+        let margin = self.horizontal_margin();
+        if column < self.left_column + margin {
+            self.left_column = column.saturating_sub(margin);
+        } else if column + margin >= self.left_column + self.width {
+            self.left_column = column + margin + 1 - self.width;
+        }
+    }
+
+    pub fn scroll(&mut self, lines: i32, line_count: u32) {
+        self.top_line = (self.top_line as i64 + lines as i64).max(0) as u32;
+        self.clamp(line_count);
+    }
+
+    /// Never scroll further than having the last line at the bottom of the screen
+    fn clamp(&mut self, line_count: u32) {
+        let max_top = line_count.saturating_sub(self.height);
+        self.top_line = self.top_line.min(max_top);
+    }
+
+    /// The closest line to `line` that is visible and outside of the scroll margin
+    pub fn clamp_line(&self, pt: &PieceTable, line: u32) -> u32 {
+        let margin = self.margin();
+        let last_line = pt.line_count().saturating_sub(1);
+
+        // Wrapped lines take up more rows, so the last line on screen depends on the content
+        let mut bottom = self.top_line;
+        let mut rows_above = 0;
+        while bottom < last_line {
+            rows_above += self.line_rows(pt, bottom);
+            if rows_above >= self.height {
+                break;
+            }
+            bottom += 1;
+        }
+
+        // No margin needed at the start and end of the buffer
+        let first = if self.top_line == 0 { 0 } else { self.top_line + margin };
+        let last = if bottom >= last_line { last_line } else { bottom.saturating_sub(margin) };
+
+        line.clamp(first.min(last), last)
+    }
+
+    /// Byte offsets of the first `count` characters on `line` and the offset after the last of them,
+    /// fewer when the line is shorter
+    fn char_offsets(pt: &PieceTable, line: u32, count: u32) -> Vec<u32> {
+        let start = pt.line_start(line).unwrap_or(0);
+        let end = start + pt.line_len(line).unwrap_or(0);
+
+        let mut offsets = Vec::new();
+        let mut chunk_start = start;
+        for chunk in pt.chunks(start, end) {
+            for (i, _) in chunk.char_indices() {
+                offsets.push(chunk_start + i as u32);
+                // The start of the next character is where the last one ends
+                if offsets.len() as u32 > count {
+                    return offsets;
+                }
+            }
+            chunk_start += chunk.len() as u32;
+        }
+
+        offsets.push(end);
+        offsets
+    }
+
+    pub fn line_rows(&self, pt: &PieceTable, line: u32) -> u32 {
+        if !self.wrap {
+            return 1;
+        }
+
+        let start = pt.line_start(line).unwrap_or(0);
+        let end = start + pt.line_len(line).unwrap_or(0);
+        let chars: usize = pt.chunks(start, end).map(|chunk| chunk.chars().count()).sum();
+        (chars as u32).div_ceil(self.width).max(1)
+    }
+
+    /// Every row that fits on the screen, starting at the top line. Only the characters that end up
+    /// on screen are looked at, so long lines cost no more than short ones.
+    pub fn rows(&self, pt: &PieceTable) -> Vec<ScreenRow> {
+        let mut rows = Vec::new();
+        let mut line = self.top_line;
+
+        while (rows.len() as u32) < self.height && line < pt.line_count() {
+            if self.wrap {
+                let rows_left = self.height - rows.len() as u32;
+                let offsets = Self::char_offsets(pt, line, rows_left.saturating_mul(self.width));
+                let chars = offsets.len() as u32 - 1;
+
+                let mut column = 0;
+                loop {
+                    let end_column = (column + self.width).min(chars);
+                    rows.push(ScreenRow {
+                        line,
+                        start: offsets[column as usize],
+                        end: offsets[end_column as usize],
+                        first_column: column,
+                    });
+
+                    column = end_column;
+                    if column >= chars || rows.len() as u32 >= self.height {
+                        break;
+                    }
+                }
+            } else {
+                let offsets = Self::char_offsets(pt, line, self.left_column.saturating_add(self.width));
+                let chars = offsets.len() as u32 - 1;
+                let first = self.left_column.min(chars);
+                let last = (self.left_column + self.width).min(chars);
+                rows.push(ScreenRow {
+                    line,
+                    start: offsets[first as usize],
+                    end: offsets[last as usize],
+                    first_column: self.left_column,
+                });
+            }
+
+            line += 1;
+        }
+
+        rows
+    }
+
+    /// Row and column on screen where `index` is drawn, `None` when it is scrolled out of view
+    pub fn screen_position(&self, pt: &PieceTable, index: u32) -> Option<(u32, u32)> {
+        let (line, _) = pt.offset_to_line_col(index);
+        let column = Cursor::column(pt, index);
+        if line < self.top_line {
+            return None;
+        }
+
+        if !self.wrap {
+            if line >= self.top_line + self.height || column < self.left_column || column > self.left_column + self.width {
+                return None;
+            }
+            return Some((line - self.top_line, column - self.left_column));
+        }
+
+        let rows_above: u32 = (self.top_line..line).map(|l| self.line_rows(pt, l)).sum();
+        // The end of a line that fills its last row stays on that row
+        let row_in_line = (column / self.width).min(self.line_rows(pt, line) - 1);
+        let row = rows_above + row_in_line;
+        if row >= self.height {
+            return None;
+        }
+
+        Some((row, column - row_in_line * self.width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::piece_table::PieceTable;
+    use crate::viewport::{ScreenRow, Viewport};
+
+    fn lines(count: u32) -> PieceTable {
+        PieceTable::init((0..count).map(|i| format!("line {i}")).collect::<Vec<_>>().join("\n"))
+    }
+
+    fn viewport(height: u32, width: u32) -> Viewport {
+        let mut viewport = Viewport::new();
+        viewport.resize(height, width);
+        viewport
+    }
+
+    #[test]
+    fn gutter_grows_with_line_count() {
+        let mut viewport = Viewport::new();
+        viewport.update_gutter(999);
+        viewport.resize(10, 80);
+        assert_eq!(viewport.gutter, 4);
+        assert_eq!(viewport.width, 76);
+        assert_eq!(viewport.line_number(8, 0), "  9 ");
+
+        viewport.update_gutter(12345);
+        assert_eq!(viewport.gutter, 6);
+        assert_eq!(viewport.line_number(8, 0), "    9 ");
+
+        viewport.number = false;
+        viewport.update_gutter(12345);
+        assert_eq!(viewport.gutter, 0);
+    }
+
+    #[test]
+    fn relative_line_numbers() {
+        let mut viewport = Viewport::new();
+        viewport.relative_number = true;
+        viewport.update_gutter(100);
+
+        assert_eq!(viewport.line_number(3, 5), "  2 ");
+        assert_eq!(viewport.line_number(8, 5), "  3 ");
+        // The cursor line keeps its own number
+        assert_eq!(viewport.line_number(5, 5), "  6 ");
+
+        viewport.number = false;
+        assert_eq!(viewport.line_number(5, 5), "  0 ");
+    }
+
+    #[test]
+    fn scroll_down_keeps_margin_below_cursor() {
+        let mut viewport = viewport(10, 80);
+
+        viewport.scroll_to_line(10, 100);
+        assert_eq!(viewport.top_line, 4);
+
+        viewport.scroll_to_line(7, 100);
+        assert_eq!(viewport.top_line, 4);
+    }
+
+    #[test]
+    fn scroll_up_keeps_margin_above_cursor() {
+        let mut viewport = viewport(10, 80);
+        viewport.top_line = 50;
+
+        viewport.scroll_to_line(52, 100);
+        assert_eq!(viewport.top_line, 49);
+
+        viewport.scroll_to_line(1, 100);
+        assert_eq!(viewport.top_line, 0);
+    }
+
+    #[test]
+    fn scroll_stops_at_end_of_buffer() {
+        let mut viewport = viewport(10, 80);
+
+        viewport.scroll_to_line(99, 100);
+        assert_eq!(viewport.top_line, 90);
+
+        viewport.scroll(20, 100);
+        assert_eq!(viewport.top_line, 90);
+
+        viewport.scroll(-200, 100);
+        assert_eq!(viewport.top_line, 0);
+    }
+
+    #[test]
+    fn clamp_line_into_view() {
+        let pt = lines(100);
+        let mut viewport = viewport(10, 80);
+        viewport.top_line = 20;
+
+        assert_eq!(viewport.clamp_line(&pt, 0), 23);
+        assert_eq!(viewport.clamp_line(&pt, 25), 25);
+        assert_eq!(viewport.clamp_line(&pt, 80), 26);
+
+        viewport.top_line = 90;
+        assert_eq!(viewport.clamp_line(&pt, 99), 99);
+    }
+
+    #[test]
+    fn clamp_line_into_view_with_wrapped_lines() {
+        let pt = PieceTable::init(vec!["a".repeat(20); 10].join("\n"));
+        let mut viewport = viewport(6, 10);
+        viewport.scroll_margin = 0;
+        viewport.wrap = true;
+        viewport.top_line = 2;
+
+        // Every line takes up two rows, so only three fit
+        assert_eq!(viewport.clamp_line(&pt, 0), 2);
+        assert_eq!(viewport.clamp_line(&pt, 9), 4);
+    }
+
+    #[test]
+    fn scroll_horizontally_to_cursor() {
+        let pt = PieceTable::init("a".repeat(100));
+        let mut viewport = viewport(10, 20);
+
+        viewport.scroll_to_cursor(&pt, 50);
+        assert_eq!(viewport.left_column, 36);
+        assert_eq!(viewport.screen_position(&pt, 50), Some((0, 14)));
+        assert_eq!(viewport.screen_position(&pt, 10), None);
+
+        viewport.scroll_to_cursor(&pt, 10);
+        assert_eq!(viewport.left_column, 5);
+    }
+
+    #[test]
+    fn rows_are_cut_at_left_column() {
+        let pt = PieceTable::init(String::from("0123456789\nab"));
+        let mut viewport = viewport(10, 4);
+        viewport.left_column = 3;
+
+        assert_eq!(viewport.rows(&pt), vec![
+            ScreenRow { line: 0, start: 3, end: 7, first_column: 3 },
+            ScreenRow { line: 1, start: 13, end: 13, first_column: 3 },
+        ]);
+    }
+
+    #[test]
+    fn wrapped_rows() {
+        let pt = PieceTable::init(String::from("0123456789\n\nab"));
+        let mut viewport = viewport(10, 4);
+        viewport.wrap = true;
+
+        assert_eq!(viewport.rows(&pt), vec![
+            ScreenRow { line: 0, start: 0, end: 4, first_column: 0 },
+            ScreenRow { line: 0, start: 4, end: 8, first_column: 4 },
+            ScreenRow { line: 0, start: 8, end: 10, first_column: 8 },
+            ScreenRow { line: 1, start: 11, end: 11, first_column: 0 },
+            ScreenRow { line: 2, start: 12, end: 14, first_column: 0 },
+        ]);
+
+        assert_eq!(viewport.screen_position(&pt, 5), Some((1, 1)));
+        assert_eq!(viewport.screen_position(&pt, 13), Some((4, 1)));
+    }
+
+    #[test]
+    fn rows_stop_at_the_screen_edges() {
+        let pt = PieceTable::init(format!("{}\nab", "\u{e9}".repeat(1000)));
+        let mut viewport = viewport(2, 4);
+        viewport.wrap = true;
+
+        assert_eq!(viewport.rows(&pt), vec![
+            ScreenRow { line: 0, start: 0, end: 8, first_column: 0 },
+            ScreenRow { line: 0, start: 8, end: 16, first_column: 4 },
+        ]);
+
+        viewport.wrap = false;
+        viewport.left_column = 2;
+        assert_eq!(viewport.rows(&pt), vec![
+            ScreenRow { line: 0, start: 4, end: 12, first_column: 2 },
+            ScreenRow { line: 1, start: 2003, end: 2003, first_column: 2 },
+        ]);
+    }
+
+    #[test]
+    fn wrapped_lines_scroll_until_cursor_is_visible() {
+        let pt = PieceTable::init(format!("{}\n{}\nend", "a".repeat(20), "b".repeat(20)));
+        let mut viewport = viewport(4, 10);
+        viewport.scroll_margin = 0;
+        viewport.wrap = true;
+
+        viewport.scroll_to_cursor(&pt, 42);
+        assert_eq!(viewport.top_line, 1);
+        assert_eq!(viewport.screen_position(&pt, 42), Some((2, 0)));
+    }
+}